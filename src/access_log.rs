@@ -0,0 +1,304 @@
+use crate::config::Config;
+use crate::errors::{GenericError, PathError};
+use crate::request::{self, Request};
+use crate::schedule;
+use crate::server::Routes;
+use serde_derive::Serialize;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::net::IpAddr;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+use warp::hyper::body::HttpBody;
+use warp::reply::{Reply, Response};
+use warp::Filter;
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    /// Common Log Format
+    Common,
+    /// Combined Log Format, the Common Log Format with the referer and user
+    /// agent
+    Combined,
+    /// One JSON object per line
+    Json,
+}
+
+impl FromStr for Format {
+    type Err = GenericError;
+
+    fn from_str(text: &str) -> Result<Format, GenericError> {
+        match text.to_lowercase().as_str() {
+            "common" => Ok(Format::Common),
+            "combined" => Ok(Format::Combined),
+            "json" => Ok(Format::Json),
+            _ => Err(format!(
+                "Unknown access log format {:?}, expected common, combined or json",
+                text
+            ))?,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct JsonEntry<'a> {
+    time: String,
+    client_ip: Option<IpAddr>,
+    method: &'a str,
+    path: String,
+    protocol: String,
+    status: u16,
+    bytes: u64,
+    referer: Option<&'a str>,
+    user_agent: Option<&'a str>,
+    duration_ms: f64,
+}
+
+/// Writes a line for every served request.
+pub struct AccessLog {
+    format: Format,
+    trusted_proxies: Vec<IpAddr>,
+    output: Mutex<Box<dyn Write + Send>>,
+}
+
+impl AccessLog {
+    /// Open the access log given in the config, or None if access logging is
+    /// disabled. `-` logs to stdout.
+    pub fn open(config: &Config) -> Result<Option<AccessLog>, GenericError> {
+        let path = match &config.access_log {
+            Some(path) => path,
+            None => return Ok(None),
+        };
+        let format = config.access_log_format.parse()?;
+        let output: Box<dyn Write + Send> = if path == Path::new("-") {
+            Box::new(io::stdout())
+        } else {
+            match OpenOptions::new().create(true).append(true).open(path) {
+                Ok(file) => Box::new(file),
+                Err(e) => Err(PathError::new(path.clone(), e.to_string()))?,
+            }
+        };
+        Ok(Some(AccessLog {
+            format,
+            trusted_proxies: config.trusted_proxies.clone(),
+            output: Mutex::new(output),
+        }))
+    }
+
+    /// Wrap `routes` so that every reply is logged.
+    pub fn wrap(self: Arc<Self>, routes: Routes) -> Routes {
+        warp::any()
+            .map(Instant::now)
+            .and(request::request())
+            .and(routes)
+            .map(
+                move |start: Instant, request: Request, reply: Box<dyn Reply>| {
+                    let response = reply.into_response();
+                    self.write(&request, &response, start.elapsed());
+                    Box::new(response) as Box<dyn Reply>
+                },
+            )
+            .boxed()
+    }
+
+    fn write(&self, request: &Request, response: &Response, elapsed: Duration) {
+        let client_ip = request.client_ip(&self.trusted_proxies);
+        let bytes = response.body().size_hint().exact().unwrap_or(0);
+        let now = SystemTime::now();
+
+        let mut line = match self.format {
+            Format::Common | Format::Combined => {
+                let mut line = format!(
+                    "{} - - [{}] \"{} {} {:?}\" {} {}",
+                    client_ip.map_or("-".to_owned(), |ip| ip.to_string()),
+                    clf_time(now),
+                    request.method(),
+                    escape(&request.path_and_query()),
+                    request.version(),
+                    response.status().as_u16(),
+                    if bytes == 0 {
+                        "-".to_owned()
+                    } else {
+                        bytes.to_string()
+                    },
+                );
+                if self.format == Format::Combined {
+                    line += &format!(
+                        " \"{}\" \"{}\"",
+                        escape(request.header("referer").unwrap_or("-")),
+                        escape(request.header("user-agent").unwrap_or("-")),
+                    );
+                }
+                line
+            }
+            Format::Json => serde_json::to_string(&JsonEntry {
+                time: schedule::rfc3339_time(now),
+                client_ip,
+                method: request.method(),
+                path: request.path_and_query(),
+                protocol: format!("{:?}", request.version()),
+                status: response.status().as_u16(),
+                bytes,
+                referer: request.header("referer"),
+                user_agent: request.header("user-agent"),
+                duration_ms: elapsed.as_secs_f64() * 1000.0,
+            })
+            .unwrap(),
+        };
+        line.push('\n');
+
+        // One write per line, so lines from different requests do not mix
+        if let Err(e) = self.output.lock().unwrap().write_all(line.as_bytes()) {
+            log::error!("Unable to write the access log: {}", e);
+        }
+    }
+}
+
+/// Time as in the Common Log Format, eg. `10/Oct/2000:13:55:36 +0000`.
+fn clf_time(time: SystemTime) -> String {
+    let (year, month, day, hour, minute, second) = schedule::utc(time);
+    format!(
+        "{:02}/{}/{}:{:02}:{:02}:{:02} +0000",
+        day,
+        MONTHS[month as usize - 1],
+        year,
+        hour,
+        minute,
+        second
+    )
+}
+
+/// Escape quotes, backslashes and control characters so a field can not
+/// break out of its quotes.
+fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if c.is_control() => out.push_str(&format!("\\x{:02x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::UNIX_EPOCH;
+    use warp::http::header::{HeaderName, HeaderValue};
+    use warp::http::{HeaderMap, StatusCode, Uri};
+
+    /// Collects everything written to the log.
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(data);
+            Ok(data.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// The line logged for a request to `uri` with `headers`, without the
+    /// time.
+    fn line(format: Format, uri: &str, headers: &[(&str, &str)]) -> String {
+        let buffer = Buffer::default();
+        let log = AccessLog {
+            format,
+            trusted_proxies: Vec::new(),
+            output: Mutex::new(Box::new(buffer.clone())),
+        };
+        let mut header_map = HeaderMap::new();
+        for (name, value) in headers {
+            header_map.insert(
+                HeaderName::from_bytes(name.as_bytes()).unwrap(),
+                HeaderValue::from_str(value).unwrap(),
+            );
+        }
+        let request = Request::new(&uri.parse::<Uri>().unwrap(), header_map);
+        let mut response = Response::new("body".into());
+        *response.status_mut() = StatusCode::NOT_FOUND;
+        log.write(&request, &response, Duration::from_millis(2));
+
+        let line = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        assert!(line.ends_with('\n'), "{:?}", line);
+        line
+    }
+
+    #[test]
+    fn clf_time() {
+        let time = |seconds| super::clf_time(UNIX_EPOCH + Duration::from_secs(seconds));
+        assert_eq!(time(0), "01/Jan/1970:00:00:00 +0000");
+        assert_eq!(time(971186136), "10/Oct/2000:13:55:36 +0000");
+        assert_eq!(time(1709164805), "29/Feb/2024:00:00:05 +0000");
+    }
+
+    #[test]
+    fn escape() {
+        assert_eq!(super::escape("plain text ä"), "plain text ä");
+        assert_eq!(super::escape(r#"a "b" \c"#), r#"a \"b\" \\c"#);
+        assert_eq!(super::escape("a\nb\tc\x1b\x7f"), "a\\x0ab\\x09c\\x1b\\x7f");
+    }
+
+    #[test]
+    fn common_and_combined() {
+        let common = line(Format::Common, "/a?b=1", &[]);
+        assert!(common.starts_with("- - - ["), "{:?}", common);
+        assert!(
+            common.ends_with("] \"GET /a?b=1 HTTP/1.1\" 404 4\n"),
+            "{:?}",
+            common
+        );
+
+        let combined = line(
+            Format::Combined,
+            "/a?q=%22",
+            &[("referer", "https://a.b/"), ("user-agent", "x \"y\"\t\\z")],
+        );
+        assert!(
+            combined.ends_with(
+                "] \"GET /a?q=%22 HTTP/1.1\" 404 4 \"https://a.b/\" \"x \\\"y\\\"\\x09\\\\z\"\n"
+            ),
+            "{:?}",
+            combined
+        );
+        // Missing headers are logged as `-`
+        assert!(line(Format::Combined, "/", &[]).ends_with(" 404 4 \"-\" \"-\"\n"));
+    }
+
+    #[test]
+    fn json() {
+        let line = line(Format::Json, "/a?b=1", &[("user-agent", "x \"y\"\t\\z")]);
+        assert_eq!(line.matches('\n').count(), 1, "{:?}", line);
+        let entry: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(entry["client_ip"], serde_json::Value::Null);
+        assert_eq!(entry["method"], "GET");
+        assert_eq!(entry["path"], "/a?b=1");
+        assert_eq!(entry["protocol"], "HTTP/1.1");
+        assert_eq!(entry["status"], 404);
+        assert_eq!(entry["bytes"], 4);
+        assert_eq!(entry["referer"], serde_json::Value::Null);
+        assert_eq!(entry["user_agent"], "x \"y\"\t\\z");
+        assert_eq!(entry["duration_ms"], 2.0);
+        assert!(
+            line.contains(r#""user_agent":"x \"y\"\t\\z""#),
+            "{:?}",
+            line
+        );
+        // RFC 3339 like `2000-10-10T13:55:36Z`
+        let time = entry["time"].as_str().unwrap();
+        assert!(time.len() == 20 && time.ends_with('Z'), "{}", time);
+    }
+}
@@ -1,3 +1,4 @@
+use std::net::IpAddr;
 use std::path::PathBuf;
 
 use argh::FromArgs;
@@ -42,10 +43,26 @@ pub struct MainArgs {
     )]
     pub port: Option<u16>,
 
+    #[argh(
+        option,
+        short = 'l',
+        description = "address to listen on, as ip:port, hostname:port or unix:/path. can be given multiple times, replaces --host and --port."
+    )]
+    pub listen: Vec<String>,
+
+    #[argh(
+        option,
+        description = "file mode of unix sockets, in octal. for example 660"
+    )]
+    pub unix_mode: Option<String>,
+
+    #[argh(option, description = "owner of unix sockets, as user or user:group")]
+    pub unix_owner: Option<String>,
+
     #[argh(
         option,
         short = 's',
-        description = "set path where to serve static content. defaults to \"static\""
+        description = "set path where to serve static content, can have several segments or be empty. defaults to \"static\""
     )]
     pub static_path: Option<String>,
 
@@ -56,6 +73,18 @@ pub struct MainArgs {
     )]
     pub static_content: Option<PathBuf>,
 
+    #[argh(
+        option,
+        description = "how symlinks in the static content are treated: follow, deny, or inside to follow only links within it. defaults to inside"
+    )]
+    pub static_symlinks: Option<String>,
+
+    #[argh(
+        option,
+        description = "preload the static content into memory, up to this size in bytes, or with a K, M or G suffix. 0 disables, which is the default"
+    )]
+    pub static_cache_size: Option<String>,
+
     #[argh(
         option,
         short = 'm',
@@ -63,10 +92,138 @@ pub struct MainArgs {
     )]
     pub mime_types: Option<PathBuf>,
 
+    #[argh(
+        option,
+        description = "http status code of the served html file. defaults to 503"
+    )]
+    pub status: Option<u16>,
+
+    #[argh(
+        option,
+        description = "value of the Retry-After header, in seconds or as an http date."
+    )]
+    pub retry_after: Option<String>,
+
+    #[argh(
+        option,
+        description = "seconds between checking the served files for changes. 0 disables, which is the default."
+    )]
+    pub reload_interval: Option<u64>,
+
+    #[argh(
+        option,
+        description = "seconds to wait for open connections to finish when shutting down. defaults to 30"
+    )]
+    pub drain_timeout: Option<u64>,
+
+    #[argh(
+        option,
+        description = "path of the health check endpoint. defaults to /_sstatic/health, empty disables"
+    )]
+    pub health_path: Option<String>,
+
+    #[argh(
+        option,
+        description = "path of the readiness endpoint. defaults to /_sstatic/ready, empty disables"
+    )]
+    pub ready_path: Option<String>,
+
+    #[argh(
+        option,
+        description = "path of the version endpoint. defaults to /_sstatic/version, empty disables"
+    )]
+    pub version_path: Option<String>,
+
+    #[argh(
+        option,
+        description = "path of the prometheus metrics endpoint, for example /metrics. disabled by default"
+    )]
+    pub metrics_path: Option<String>,
+
+    #[argh(
+        option,
+        description = "file to write the access log to, or - for stdout. disabled by default"
+    )]
+    pub access_log: Option<PathBuf>,
+
+    #[argh(
+        option,
+        description = "format of the access log: common, combined or json. defaults to combined"
+    )]
+    pub access_log_format: Option<String>,
+
+    #[argh(
+        option,
+        description = "level of the application logs: off, error, warn, info, debug or trace. defaults to info"
+    )]
+    pub log_level: Option<String>,
+
+    #[argh(
+        option,
+        description = "path to the tls certificate chain, in PEM format"
+    )]
+    pub tls_cert: Option<PathBuf>,
+
+    #[argh(option, description = "path to the tls private key, in PEM format")]
+    pub tls_key: Option<PathBuf>,
+
+    #[argh(
+        option,
+        description = "port of a plain http listener that redirects to https. requires --tls-cert and --tls-key"
+    )]
+    pub redirect_port: Option<u16>,
+
     #[argh(
         option,
         short = 'o',
         description = "path a config.toml file containing configurations."
     )]
     pub config_path: Option<PathBuf>,
+
+    #[argh(
+        switch,
+        description = "do not look for a config file in the default locations."
+    )]
+    pub no_config: bool,
+
+    #[argh(
+        option,
+        description = "address of a proxy trusted to set X-Forwarded-For. can be given multiple times."
+    )]
+    pub trusted_proxy: Vec<IpAddr>,
+
+    #[argh(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(FromArgs, Debug, Clone)]
+#[argh(subcommand)]
+pub enum Command {
+    Check(CheckArgs),
+    Render(RenderArgs),
+}
+
+#[derive(FromArgs, Debug, Clone)]
+/// Load the config, templates and mime types and print the effective config,
+/// without serving anything.
+#[argh(subcommand, name = "check")]
+pub struct CheckArgs {}
+
+#[derive(FromArgs, Debug, Clone)]
+/// Print the page that would be served for a request, without serving
+/// anything.
+#[argh(subcommand, name = "render")]
+pub struct RenderArgs {
+    #[argh(option, description = "host header of the request")]
+    pub host: Option<String>,
+
+    #[argh(option, description = "user agent of the request")]
+    pub user_agent: Option<String>,
+
+    #[argh(
+        option,
+        default = "String::from(\"/\")",
+        description = "path and query of the request. defaults to /"
+    )]
+    pub path: String,
 }
@@ -0,0 +1,442 @@
+use crate::cache_control::CachePolicy;
+use crate::conditional::Validators;
+use crate::files::{self, Mimetypes, Symlinks};
+use crate::reload::{self, Fingerprint, POLL_INTERVAL};
+use crate::request::Request;
+use crate::site::SiteHandle;
+use log::{error, info, warn};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use warp::http::header::{HeaderValue, CACHE_CONTROL, CONTENT_TYPE};
+use warp::hyper::body::Bytes;
+use warp::hyper::Body;
+use warp::reply::{Reply, Response};
+
+/// A file kept in memory, along with the headers it is served with.
+struct Entry {
+    body: Bytes,
+    content_type: HeaderValue,
+    cache_control: Option<HeaderValue>,
+    validators: Validators,
+}
+
+#[derive(Default)]
+struct State {
+    entries: HashMap<String, Entry>,
+    /// Modification times and sizes of every file under the root when the
+    /// entries were read.
+    fingerprint: Fingerprint,
+    /// Whether every file fit in the cache, so a miss means there is no file.
+    complete: bool,
+}
+
+/// The result of looking up a request path in the cache.
+pub enum Lookup {
+    Hit(Box<dyn Reply>),
+    /// There is no file at the path.
+    Missing,
+    /// The file was not cached, it may still be on disk.
+    Unknown,
+}
+
+/// The files of `static_content` preloaded into memory, up to `limit` bytes.
+pub struct Cache {
+    root: PathBuf,
+    symlinks: Symlinks,
+    mimetypes: Mimetypes,
+    cache_control: Arc<CachePolicy>,
+    limit: u64,
+    /// Whether the root is a single file rather than a directory.
+    single: bool,
+    state: RwLock<State>,
+}
+
+impl Cache {
+    /// Read the files under `root`, which must be canonical.
+    pub fn load(
+        root: &Path,
+        symlinks: Symlinks,
+        mimetypes: &Mimetypes,
+        cache_control: Arc<CachePolicy>,
+        limit: u64,
+    ) -> Cache {
+        let cache = Cache {
+            root: root.to_owned(),
+            symlinks,
+            mimetypes: mimetypes.clone(),
+            cache_control,
+            limit,
+            single: root.is_file(),
+            state: RwLock::new(State::default()),
+        };
+        let files = cache.files();
+        let state = cache.read(&files);
+        info!(
+            "Cached {} of {} static files in memory",
+            state.entries.len(),
+            files.len()
+        );
+        *cache.state.write().unwrap() = state;
+        cache
+    }
+
+    pub fn get(&self, tail: &str, request: &Request) -> Lookup {
+        let state = self.state.read().unwrap();
+        // A single file is served for every path
+        let key = if self.single {
+            String::new()
+        } else {
+            match files::segments(tail) {
+                Some(segments) => segments.join("/"),
+                None => return Lookup::Missing,
+            }
+        };
+        match state.entries.get(&key) {
+            Some(entry) => {
+                let mut response = if entry.validators.fresh(request) {
+                    entry.validators.not_modified()
+                } else {
+                    let mut response = Response::new(Body::from(entry.body.clone()));
+                    let headers = response.headers_mut();
+                    headers.insert(CONTENT_TYPE, entry.content_type.clone());
+                    entry.validators.apply(headers);
+                    response
+                };
+                if let Some(value) = &entry.cache_control {
+                    response.headers_mut().insert(CACHE_CONTROL, value.clone());
+                }
+                Lookup::Hit(Box::new(response))
+            }
+            None if state.complete => Lookup::Missing,
+            None => Lookup::Unknown,
+        }
+    }
+
+    /// Read the files again if any of them changed, or files were added or
+    /// removed.
+    pub fn refresh(&self) {
+        let files = self.files();
+        if self.fingerprint(&files) == self.state.read().unwrap().fingerprint {
+            return;
+        }
+        info!("Static content changed, refreshing the cache");
+        let state = self.read(&files);
+        *self.state.write().unwrap() = state;
+    }
+
+    fn read(&self, files: &[(String, PathBuf)]) -> State {
+        let mut state = State {
+            fingerprint: self.fingerprint(files),
+            complete: true,
+            ..State::default()
+        };
+        // Files reached through several links are kept in memory once
+        let mut bodies: HashMap<&Path, (Bytes, Validators)> = HashMap::new();
+        let mut size = 0;
+        for (key, path) in files {
+            let (body, validators) = match bodies.get(path.as_path()) {
+                Some(body) => body.clone(),
+                None => {
+                    let metadata = match fs::metadata(path) {
+                        Ok(metadata) => metadata,
+                        Err(e) => {
+                            warn!("Unable to cache {}: {}", path.display(), e);
+                            state.complete = false;
+                            continue;
+                        }
+                    };
+                    // Files that do not fit are not read at all
+                    if size + metadata.len() > self.limit {
+                        state.complete = false;
+                        continue;
+                    }
+                    let content = match fs::read(path) {
+                        Ok(content) => content,
+                        Err(e) => {
+                            warn!("Unable to cache {}: {}", path.display(), e);
+                            state.complete = false;
+                            continue;
+                        }
+                    };
+                    // The file may have grown since
+                    if size + content.len() as u64 > self.limit {
+                        state.complete = false;
+                        continue;
+                    }
+                    size += content.len() as u64;
+                    let validators = Validators::new(&content, metadata.modified().ok());
+                    let body = (Bytes::from(content), validators);
+                    bodies.insert(path, body.clone());
+                    body
+                }
+            };
+            let mime = self.mimetypes.for_path(path);
+            // The single file is matched by its own name
+            let name = if self.single {
+                path.file_name()
+                    .map(|name| name.to_string_lossy().into_owned())
+                    .unwrap_or_default()
+            } else {
+                key.clone()
+            };
+            let cache_control = self.cache_control.for_file(&name, &mime).cloned();
+            let content_type = HeaderValue::from_str(&mime)
+                .unwrap_or_else(|_| HeaderValue::from_static("text/plain"));
+            state.entries.insert(
+                key.clone(),
+                Entry {
+                    body,
+                    content_type,
+                    cache_control,
+                    validators,
+                },
+            );
+        }
+        if !state.complete {
+            warn!(
+                "Only {} of {} static files fit in static_cache_size, the rest are read from disk",
+                state.entries.len(),
+                files.len()
+            );
+        }
+        state
+    }
+
+    /// Modification times and sizes of `files`, by the path they are found
+    /// at under the root, so renamed links count as changes too.
+    fn fingerprint(&self, files: &[(String, PathBuf)]) -> Fingerprint {
+        let paths: Vec<PathBuf> = files
+            .iter()
+            .map(|(key, path)| {
+                if self.single {
+                    path.clone()
+                } else {
+                    self.root.join(key)
+                }
+            })
+            .collect();
+        reload::fingerprint(&paths)
+    }
+
+    /// Every file that can be served from the root, by the request path that
+    /// serves it.
+    fn files(&self) -> Vec<(String, PathBuf)> {
+        let mut files = Vec::new();
+        if self.single {
+            files.push((String::new(), self.root.clone()));
+        } else {
+            let mut parents = vec![self.root.clone()];
+            self.walk(&self.root, "", &mut parents, &mut files);
+        }
+        files
+    }
+
+    fn walk(
+        &self,
+        dir: &Path,
+        prefix: &str,
+        parents: &mut Vec<PathBuf>,
+        files: &mut Vec<(String, PathBuf)>,
+    ) {
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(_) => return,
+        };
+        for entry in entries.filter_map(|entry| entry.ok()) {
+            let name = match entry.file_name().into_string() {
+                Ok(name) => name,
+                Err(_) => continue,
+            };
+            let is_link = entry
+                .file_type()
+                .map(|file_type| file_type.is_symlink())
+                .unwrap_or(true);
+            if is_link && self.symlinks == Symlinks::Deny {
+                continue;
+            }
+            let canonical = match entry.path().canonicalize() {
+                Ok(canonical) => canonical,
+                Err(_) => continue,
+            };
+            if self.symlinks != Symlinks::Follow && !canonical.starts_with(&self.root) {
+                continue;
+            }
+
+            let key = if prefix.is_empty() {
+                name
+            } else {
+                format!("{}/{}", prefix, name)
+            };
+            if canonical.is_dir() {
+                // A link to a parent directory would otherwise be walked
+                // forever
+                if !parents.contains(&canonical) {
+                    parents.push(canonical);
+                    self.walk(&entry.path(), &key, parents, files);
+                    parents.pop();
+                }
+            } else {
+                files.push((key, canonical));
+            }
+        }
+    }
+}
+
+/// Keep the cache of the current site up to date with the files on disk.
+pub async fn watch(handle: SiteHandle) {
+    loop {
+        tokio::time::sleep(POLL_INTERVAL).await;
+        let site = handle.current();
+        if site.cache.is_none() {
+            continue;
+        }
+        // Walking and reading the files blocks
+        let refreshed = tokio::task::spawn_blocking(move || {
+            if let Some(cache) = &site.cache {
+                cache.refresh();
+            }
+        })
+        .await;
+        if let Err(e) = refreshed {
+            error!("Refreshing the static file cache failed: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache_control::CacheConfig;
+    use crate::files::tests::TempDir;
+    use crate::request;
+    use std::os::unix::fs::symlink;
+
+    fn load(root: &Path, symlinks: Symlinks, limit: u64) -> Cache {
+        let policy = CachePolicy::new(&CacheConfig::default()).unwrap();
+        Cache::load(
+            root,
+            symlinks,
+            &Mimetypes::default(),
+            Arc::new(policy),
+            limit,
+        )
+    }
+
+    /// What looking up each of `tails` gives, as the body of a hit or
+    /// `missing` and `unknown`.
+    async fn lookup(cache: &Cache, tails: &[&str]) -> Vec<String> {
+        let request = warp::test::request()
+            .filter(&request::request())
+            .await
+            .unwrap();
+        let mut results = Vec::new();
+        for tail in tails {
+            results.push(match cache.get(tail, &request) {
+                Lookup::Hit(reply) => {
+                    let body = warp::hyper::body::to_bytes(reply.into_response().into_body());
+                    String::from_utf8(body.await.unwrap().to_vec()).unwrap()
+                }
+                Lookup::Missing => "missing".to_owned(),
+                Lookup::Unknown => "unknown".to_owned(),
+            });
+        }
+        results
+    }
+
+    #[tokio::test]
+    async fn complete_cache() {
+        let dir = TempDir::new("cache-complete");
+        dir.write("static/a.txt", "a");
+        dir.write("static/sub/b.txt", "b");
+        let cache = load(&dir.0.join("static"), Symlinks::Inside, 1 << 20);
+        assert_eq!(
+            lookup(
+                &cache,
+                &[
+                    "a.txt",
+                    "sub/b.txt",
+                    "sub%2Fb.txt",
+                    "c.txt",
+                    "sub",
+                    "../a.txt"
+                ]
+            )
+            .await,
+            vec!["a", "b", "missing", "missing", "missing", "missing"]
+        );
+    }
+
+    #[tokio::test]
+    async fn size_limit() {
+        let dir = TempDir::new("cache-limit");
+        dir.write("static/a.txt", "same");
+        dir.write("static/b.txt", "same");
+        dir.write("static/big.txt", "0123456789");
+        let cache = load(&dir.0.join("static"), Symlinks::Inside, 6);
+        let mut results = lookup(&cache, &["a.txt", "b.txt", "big.txt", "c.txt"]).await;
+        // Whichever small file is read first fits, the rest may still be on
+        // disk, so a miss is not conclusive
+        assert_eq!(results.pop().unwrap(), "unknown");
+        assert_eq!(results.pop().unwrap(), "unknown");
+        results.sort();
+        assert_eq!(results, vec!["same", "unknown"]);
+    }
+
+    #[tokio::test]
+    async fn nothing_fits() {
+        let dir = TempDir::new("cache-empty");
+        dir.write("static/a.txt", "a");
+        let cache = load(&dir.0.join("static"), Symlinks::Inside, 0);
+        assert_eq!(lookup(&cache, &["a.txt"]).await, vec!["unknown"]);
+    }
+
+    #[tokio::test]
+    async fn symlink_policies() {
+        let outside = TempDir::new("cache-symlinks-outside");
+        let secret = outside.write("secret.txt", "secret");
+        let dir = TempDir::new("cache-symlinks");
+        let file = dir.write("static/a.txt", "a");
+        let root = dir.0.join("static");
+        symlink(&file, root.join("inside.txt")).unwrap();
+        symlink(&secret, root.join("outside.txt")).unwrap();
+        symlink(&outside.0, root.join("outside-dir")).unwrap();
+        // A link back to the root must not be walked forever
+        symlink(&root, root.join("loop")).unwrap();
+
+        let tails = [
+            "a.txt",
+            "inside.txt",
+            "outside.txt",
+            "outside-dir/secret.txt",
+            "loop/a.txt",
+        ];
+        let follow = load(&root, Symlinks::Follow, 1 << 20);
+        assert_eq!(
+            lookup(&follow, &tails).await,
+            vec!["a", "a", "secret", "secret", "missing"]
+        );
+        let inside = load(&root, Symlinks::Inside, 1 << 20);
+        assert_eq!(
+            lookup(&inside, &tails).await,
+            vec!["a", "a", "missing", "missing", "missing"]
+        );
+        let deny = load(&root, Symlinks::Deny, 1 << 20);
+        assert_eq!(
+            lookup(&deny, &tails).await,
+            vec!["a", "missing", "missing", "missing", "missing"]
+        );
+    }
+
+    #[tokio::test]
+    async fn single_file() {
+        let dir = TempDir::new("cache-single");
+        let file = dir.write("page.txt", "page");
+        let cache = load(&file, Symlinks::Inside, 1 << 20);
+        assert_eq!(
+            lookup(&cache, &["", "a.txt", "x/y"]).await,
+            vec!["page", "page", "page"]
+        );
+    }
+}
@@ -0,0 +1,281 @@
+use crate::errors::GenericError;
+use serde_derive::{Deserialize, Serialize};
+use warp::http::header::HeaderValue;
+
+/// The `[cache]` table of the config.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct CacheConfig {
+    /// `Cache-Control` of the maintenance page, none if empty.
+    #[serde(default = "default_page")]
+    pub page: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub rules: Vec<RuleConfig>,
+}
+
+impl Default for CacheConfig {
+    fn default() -> CacheConfig {
+        CacheConfig {
+            page: default_page(),
+            rules: Vec::new(),
+        }
+    }
+}
+
+fn default_page() -> String {
+    String::from("no-store")
+}
+
+/// A `[[cache.rules]]` entry of the config. Every condition given must match
+/// for the rule to apply.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct RuleConfig {
+    /// Glob of the path under `static_path`. A glob without a slash is
+    /// matched against the file name only.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    /// Glob of the mime type, eg. `image/*`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mime: Option<String>,
+    /// Whether the file name carries a content hash, like `app.3f9a0c1e.js`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fingerprinted: Option<bool>,
+    pub cache_control: String,
+}
+
+struct Rule {
+    path: Option<String>,
+    mime: Option<String>,
+    fingerprinted: Option<bool>,
+    value: HeaderValue,
+}
+
+impl Rule {
+    fn matches(&self, path: &str, mime: &str) -> bool {
+        let name = path.rsplit('/').next().unwrap_or(path);
+        let path_matches = match &self.path {
+            Some(glob) if glob.contains('/') => matches(glob.trim_start_matches('/'), path),
+            Some(glob) => matches(glob, name),
+            None => true,
+        };
+        let mime_matches = match &self.mime {
+            Some(glob) => matches(glob, mime),
+            None => true,
+        };
+        let fingerprint_matches = match self.fingerprinted {
+            Some(fingerprinted) => fingerprinted == is_fingerprinted(name),
+            None => true,
+        };
+        path_matches && mime_matches && fingerprint_matches
+    }
+}
+
+/// The `Cache-Control` headers of the page and the static files.
+pub struct CachePolicy {
+    page: Option<HeaderValue>,
+    rules: Vec<Rule>,
+}
+
+impl CachePolicy {
+    pub fn new(config: &CacheConfig) -> Result<CachePolicy, GenericError> {
+        let page = match config.page.trim() {
+            "" => None,
+            page => Some(header_value("cache.page", page)?),
+        };
+        let mut rules = Vec::new();
+        for rule in &config.rules {
+            rules.push(Rule {
+                path: rule.path.clone(),
+                mime: rule.mime.clone(),
+                fingerprinted: rule.fingerprinted,
+                value: header_value("cache_control", rule.cache_control.trim())?,
+            });
+        }
+        Ok(CachePolicy { page, rules })
+    }
+
+    pub fn page(&self) -> Option<&HeaderValue> {
+        self.page.as_ref()
+    }
+
+    /// The `Cache-Control` of the first rule that matches the static file at
+    /// `path`, relative to `static_content`.
+    pub fn for_file(&self, path: &str, mime: &str) -> Option<&HeaderValue> {
+        self.rules
+            .iter()
+            .find(|rule| rule.matches(path, mime))
+            .map(|rule| &rule.value)
+    }
+}
+
+fn header_value(key: &str, value: &str) -> Result<HeaderValue, GenericError> {
+    match HeaderValue::from_str(value) {
+        Ok(value) if !value.is_empty() => Ok(value),
+        _ => Err(format!("{} {:?} is not a valid header value", key, value))?,
+    }
+}
+
+/// Match `text` against a glob, where `*` matches anything but a slash, `**`
+/// matches anything and `?` matches a single character.
+fn matches(glob: &str, text: &str) -> bool {
+    let glob: Vec<char> = glob.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    matches_from(&glob, &text)
+}
+
+fn matches_from(glob: &[char], text: &[char]) -> bool {
+    match glob {
+        [] => text.is_empty(),
+        ['*', '*', rest @ ..] => {
+            // `**/` also matches no directories at all
+            let no_directories = match rest {
+                ['/', after @ ..] => matches_from(after, text),
+                _ => false,
+            };
+            no_directories || (0..=text.len()).any(|i| matches_from(rest, &text[i..]))
+        }
+        ['*', rest @ ..] => (0..=text.len())
+            .take_while(|&i| i == 0 || text[i - 1] != '/')
+            .any(|i| matches_from(rest, &text[i..])),
+        ['?', rest @ ..] => match text {
+            [c, text @ ..] if *c != '/' => matches_from(rest, text),
+            _ => false,
+        },
+        [c, rest @ ..] => match text {
+            [t, text @ ..] if t == c => matches_from(rest, text),
+            _ => false,
+        },
+    }
+}
+
+/// Whether a part of the file name, between dots, dashes or underscores,
+/// looks like a content hash of at least 8 hex digits.
+fn is_fingerprinted(name: &str) -> bool {
+    let mut parts: Vec<&str> = name.split(&['.', '-', '_'][..]).collect();
+    // The first part is the name and the last one the extension
+    if parts.len() < 3 {
+        return false;
+    }
+    parts.pop();
+    parts.iter().skip(1).any(|part| {
+        part.len() >= 8
+            && part.chars().all(|c| c.is_ascii_hexdigit())
+            && part.chars().any(|c| c.is_ascii_digit())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(path: Option<&str>, mime: Option<&str>, fingerprinted: Option<bool>) -> Rule {
+        Rule {
+            path: path.map(str::to_owned),
+            mime: mime.map(str::to_owned),
+            fingerprinted,
+            value: HeaderValue::from_static("max-age=60"),
+        }
+    }
+
+    #[test]
+    fn stars() {
+        assert!(matches("*.css", "app.css"));
+        assert!(!matches("*.css", "css/app.css"));
+        assert!(matches("css/*", "css/app.css"));
+        assert!(!matches("css/*", "css/vendor/app.css"));
+        assert!(matches("css/**", "css/vendor/app.css"));
+        assert!(matches("**/*.css", "css/vendor/app.css"));
+        assert!(matches("**/*.css", "app.css"));
+        assert!(matches("a/**/b", "a/b"));
+        assert!(matches("a/**/b", "a/x/y/b"));
+        assert!(!matches("a/**/b", "a/xb"));
+    }
+
+    #[test]
+    fn question_mark() {
+        assert!(matches("v?.js", "v1.js"));
+        assert!(!matches("v?.js", "v10.js"));
+        assert!(!matches("v?.js", "v.js"));
+        assert!(!matches("a?b", "a/b"));
+    }
+
+    #[test]
+    fn name_globs_match_at_any_depth() {
+        let fonts = rule(Some("*.woff2"), None, None);
+        assert!(fonts.matches("font.woff2", "font/woff2"));
+        assert!(fonts.matches("assets/fonts/font.woff2", "font/woff2"));
+        assert!(!fonts.matches("assets/font.woff", "font/woff"));
+
+        // Globs with a slash match the whole path
+        let images = rule(Some("/img/*.png"), None, None);
+        assert!(images.matches("img/a.png", "image/png"));
+        assert!(!images.matches("other/img/a.png", "image/png"));
+    }
+
+    #[test]
+    fn mime_globs() {
+        let images = rule(None, Some("image/*"), None);
+        assert!(images.matches("a.png", "image/png"));
+        assert!(images.matches("b/c.svg", "image/svg+xml"));
+        assert!(!images.matches("a.css", "text/css"));
+        assert!(!rule(None, Some("text/css"), None).matches("a.js", "text/javascript"));
+    }
+
+    #[test]
+    fn every_condition_must_match() {
+        let scripts = rule(Some("*.js"), Some("text/*"), Some(true));
+        assert!(scripts.matches("app.3f9a0c1e.js", "text/javascript"));
+        assert!(!scripts.matches("app.js", "text/javascript"));
+        assert!(!scripts.matches("app.3f9a0c1e.js", "application/javascript"));
+        assert!(!scripts.matches("app.3f9a0c1e.css", "text/css"));
+    }
+
+    #[test]
+    fn fingerprints() {
+        assert!(is_fingerprinted("app.3f9a0c1e.js"));
+        assert!(is_fingerprinted("app-3F9A0C1E.js"));
+        assert!(is_fingerprinted("app_0123456789abcdef0123.min.js"));
+        // Too short, no digits, not hex, or not between the name and extension
+        assert!(!is_fingerprinted("app.3f9a0c1.js"));
+        assert!(!is_fingerprinted("app.deadbeef.js"));
+        assert!(!is_fingerprinted("app.3f9a0c1g.js"));
+        assert!(!is_fingerprinted("12345678.js"));
+        assert!(!is_fingerprinted("app.12345678"));
+        assert!(!is_fingerprinted("app.js"));
+    }
+
+    #[test]
+    fn first_matching_rule_wins() {
+        let config = CacheConfig {
+            page: String::new(),
+            rules: vec![
+                RuleConfig {
+                    path: None,
+                    mime: None,
+                    fingerprinted: Some(true),
+                    cache_control: "max-age=31536000, immutable".to_owned(),
+                },
+                RuleConfig {
+                    path: Some("*.js".to_owned()),
+                    mime: None,
+                    fingerprinted: None,
+                    cache_control: "no-cache".to_owned(),
+                },
+            ],
+        };
+        let policy = CachePolicy::new(&config).unwrap();
+        assert_eq!(policy.page(), None);
+        assert_eq!(
+            policy
+                .for_file("app.3f9a0c1e.js", "text/javascript")
+                .unwrap(),
+            "max-age=31536000, immutable"
+        );
+        assert_eq!(
+            policy.for_file("app.js", "text/javascript").unwrap(),
+            "no-cache"
+        );
+        assert_eq!(policy.for_file("app.css", "text/css"), None);
+    }
+}
@@ -0,0 +1,85 @@
+use crate::args::RenderArgs;
+use crate::errors::GenericError;
+use crate::files::Mimetypes;
+use crate::request::Request;
+use crate::site::Site;
+use std::io::{self, Write};
+use warp::http::header::{HeaderMap, HeaderValue, HOST, USER_AGENT};
+use warp::http::Uri;
+use warp::hyper::body;
+use warp::reply::Response;
+
+/// Print the effective config, the mime types in use and the headers the
+/// page is served with.
+pub async fn check(site: &Site) -> Result<(), GenericError> {
+    let config = toml::Value::try_from(&site.config)
+        .and_then(|config| toml::to_string(&config))
+        .map_err(|e| format!("Unable to print the config: {}", e))?;
+    println!("Effective config:");
+    // Tables are printed after the plain values, and their keys are not
+    // config fields
+    let mut in_table = false;
+    for line in config.lines() {
+        let key = if line.starts_with('[') {
+            in_table = true;
+            line.trim_matches(|c| c == '[' || c == ']')
+        } else if in_table {
+            ""
+        } else {
+            line.split(" = ").next().unwrap_or("")
+        };
+        match site.config.sources.get(key) {
+            Some(source) => println!("{}  # {}", line, source),
+            None => println!("{}", line),
+        }
+    }
+
+    match Mimetypes::try_fetch(&site.config.mime_types) {
+        Ok(_) => println!("Mime types: {:?}", site.config.mime_types),
+        Err(e) => println!("Mime types: built-in, as reading failed: {}", e),
+    }
+
+    let response = reply(site, None, None, "/")?;
+    println!("Status: {}", response.status());
+    for (name, value) in response.headers() {
+        println!("{}: {}", name, value.to_str().unwrap_or("(not text)"));
+    }
+    Ok(())
+}
+
+/// Print the body of the page that would be served for the request.
+pub async fn render(site: &Site, args: &RenderArgs) -> Result<(), GenericError> {
+    let response = reply(
+        site,
+        args.host.as_deref(),
+        args.user_agent.as_deref(),
+        &args.path,
+    )?;
+    let body = body::to_bytes(response.into_body())
+        .await
+        .map_err(|e| format!("Unable to render the page: {}", e))?;
+    io::stdout().write_all(&body)?;
+    Ok(())
+}
+
+fn reply(
+    site: &Site,
+    host: Option<&str>,
+    user_agent: Option<&str>,
+    path: &str,
+) -> Result<Response, GenericError> {
+    let uri = match path.parse::<Uri>() {
+        Ok(uri) if path.starts_with('/') => uri,
+        _ => Err(format!("Invalid request path {:?}", path))?,
+    };
+    let mut headers = HeaderMap::new();
+    for (name, value) in vec![(HOST, host), (USER_AGENT, user_agent)] {
+        if let Some(value) = value {
+            match HeaderValue::from_str(value) {
+                Ok(value) => headers.insert(name, value),
+                Err(_) => Err(format!("Invalid {} {:?}", name, value))?,
+            };
+        }
+    }
+    Ok(site.reply(&Request::new(&uri, headers)))
+}
@@ -0,0 +1,78 @@
+use crate::request::Request;
+use data_encoding::BASE64;
+use ring::digest;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use warp::http::header::{HeaderMap, HeaderValue, ETAG, LAST_MODIFIED};
+use warp::http::StatusCode;
+use warp::hyper::Body;
+use warp::reply::Response;
+
+/// The `ETag` and `Last-Modified` of a response, for answering conditional
+/// requests.
+#[derive(Clone)]
+pub struct Validators {
+    /// Strong entity tag, with the quotes.
+    etag: String,
+    last_modified: Option<SystemTime>,
+}
+
+impl Validators {
+    pub fn new(content: &[u8], last_modified: Option<SystemTime>) -> Validators {
+        let digest = digest::digest(&digest::SHA256, content);
+        Validators {
+            etag: format!("\"{}\"", BASE64.encode(digest.as_ref())),
+            last_modified: last_modified.map(whole_seconds),
+        }
+    }
+
+    /// Whether the copy the client already has, as described by
+    /// `If-None-Match` or `If-Modified-Since`, is still current.
+    pub fn fresh(&self, request: &Request) -> bool {
+        if !matches!(request.method(), "GET" | "HEAD") {
+            return false;
+        }
+        // If-Modified-Since is only used when If-None-Match is not given
+        if let Some(if_none_match) = request.header("if-none-match") {
+            return if_none_match.split(',').any(|etag| {
+                let etag = etag.trim();
+                etag == "*" || etag.trim_start_matches("W/") == self.etag
+            });
+        }
+        match (request.header("if-modified-since"), self.last_modified) {
+            (Some(since), Some(last_modified)) => match httpdate::parse_http_date(since) {
+                Ok(since) => last_modified <= since,
+                Err(_) => false,
+            },
+            _ => false,
+        }
+    }
+
+    /// Add the `ETag` and `Last-Modified` headers.
+    pub fn apply(&self, headers: &mut HeaderMap) {
+        if let Ok(etag) = HeaderValue::from_str(&self.etag) {
+            headers.insert(ETAG, etag);
+        }
+        if let Some(last_modified) = self.last_modified {
+            if let Ok(value) = HeaderValue::from_str(&httpdate::fmt_http_date(last_modified)) {
+                headers.insert(LAST_MODIFIED, value);
+            }
+        }
+    }
+
+    /// A `304 Not Modified` response carrying the validators.
+    pub fn not_modified(&self) -> Response {
+        let mut response = Response::new(Body::empty());
+        *response.status_mut() = StatusCode::NOT_MODIFIED;
+        self.apply(response.headers_mut());
+        response
+    }
+}
+
+/// HTTP dates have a resolution of a second, so anything finer would make
+/// every file look modified.
+fn whole_seconds(time: SystemTime) -> SystemTime {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(duration) => UNIX_EPOCH + Duration::from_secs(duration.as_secs()),
+        Err(_) => time,
+    }
+}
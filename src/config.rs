@@ -1,12 +1,24 @@
+use crate::access_log;
 use crate::args::MainArgs;
-use crate::errors::GenericError;
+use crate::cache_control::{CacheConfig, CachePolicy};
+use crate::errors::{GenericError, PathError};
+use crate::files::Symlinks;
+use crate::schedule::WindowConfig;
+use crate::variables::Value;
+use log::LevelFilter;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
 use std::env;
+use std::fmt;
 use std::fs;
+use std::net::IpAddr;
 use std::path::PathBuf;
+use std::str::FromStr;
+use warp::http::StatusCode;
 
-use serde_derive::Deserialize;
+use serde_derive::{Deserialize, Serialize};
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct Config {
     pub html: PathBuf,
     pub css: Option<PathBuf>,
@@ -14,12 +26,158 @@ pub struct Config {
     pub unsafe_inline: bool,
     pub host: String,
     pub port: u16,
+    pub listen: Vec<String>,
+    pub unix_mode: Option<String>,
+    pub unix_owner: Option<String>,
     pub static_path: String,
     pub static_content: Option<PathBuf>,
+    pub static_symlinks: String,
+    pub static_cache_size: String,
+    pub cache: CacheConfig,
     pub mime_types: PathBuf,
+    pub trusted_proxies: Vec<IpAddr>,
+    pub defaults: HashMap<String, Value>,
+    pub status: u16,
+    pub retry_after: Option<String>,
+    pub schedule: Vec<WindowConfig>,
+    pub reload_interval: u64,
+    pub drain_timeout: u64,
+    pub health_path: String,
+    pub ready_path: String,
+    pub version_path: String,
+    pub metrics_path: String,
+    pub access_log: Option<PathBuf>,
+    pub access_log_format: String,
+    pub log_level: String,
+    pub tls_cert: Option<PathBuf>,
+    pub tls_key: Option<PathBuf>,
+    pub redirect_port: Option<u16>,
+    /// Where each value came from.
+    #[serde(skip)]
+    pub sources: BTreeMap<&'static str, Source>,
+}
+
+/// The layer of configuration a value was taken from.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Source {
+    Default,
+    /// The environment variable with the given name.
+    Env(&'static str),
+    File(PathBuf),
+    Cmd,
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::Default => write!(f, "default"),
+            Source::Env(name) => write!(f, "environment variable {}", name),
+            Source::File(path) => write!(f, "config file {}", path.display()),
+            Source::Cmd => write!(f, "command line"),
+        }
+    }
+}
+
+impl Config {
+    /// Values that are present but can not be used.
+    fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if let Err(e) = self.static_symlinks.parse::<Symlinks>() {
+            problems.push(("static_symlinks", e.to_string()));
+        }
+        if self.static_cache_bytes().is_none() {
+            problems.push((
+                "static_cache_size",
+                format!(
+                    "static_cache_size {:?} is not a size, expected eg. 0, 65536 or 64M",
+                    self.static_cache_size
+                ),
+            ));
+        }
+        if let Err(GenericError::StrError(e)) = CachePolicy::new(&self.cache) {
+            problems.push(("cache", e));
+        }
+        if StatusCode::from_u16(self.status).is_err() {
+            problems.push((
+                "status",
+                format!("status {} is not a valid status code", self.status),
+            ));
+        }
+        if self.log_level.parse::<LevelFilter>().is_err() {
+            problems.push((
+                "log_level",
+                format!(
+                    "log_level {:?} is unknown, expected off, error, warn, info, debug or trace",
+                    self.log_level
+                ),
+            ));
+        }
+        if let Err(e) = self.access_log_format.parse::<access_log::Format>() {
+            problems.push(("access_log_format", e.to_string()));
+        }
+        if let Some(mode) = &self.unix_mode {
+            if self.unix_mode().is_none() {
+                problems.push((
+                    "unix_mode",
+                    format!("unix_mode {:?} is not an octal mode", mode),
+                ));
+            }
+        }
+        match (&self.tls_cert, &self.tls_key) {
+            (Some(_), None) => {
+                problems.push(("tls_cert", "tls_cert is set without tls_key".to_owned()))
+            }
+            (None, Some(_)) => {
+                problems.push(("tls_key", "tls_key is set without tls_cert".to_owned()))
+            }
+            _ => {}
+        }
+        if self.redirect_port.is_some() && self.tls_cert.is_none() {
+            problems.push((
+                "redirect_port",
+                "redirect_port needs tls_cert and tls_key".to_owned(),
+            ));
+        }
+        problems
+            .into_iter()
+            .map(|(field, problem)| match self.sources.get(field) {
+                Some(Source::Default) | None => problem,
+                Some(source) => format!("{} (from {})", problem, source),
+            })
+            .collect()
+    }
+
+    /// Size limit of the static file cache in bytes, parsed from
+    /// `static_cache_size` which may end in K, M or G.
+    pub fn static_cache_bytes(&self) -> Option<u64> {
+        let text = self.static_cache_size.trim();
+        let split = text
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(text.len());
+        let (number, unit) = text.split_at(split);
+        let multiplier = match unit.trim().to_lowercase().as_str() {
+            "" | "b" => 1,
+            "k" | "kb" | "kib" => 1 << 10,
+            "m" | "mb" | "mib" => 1 << 20,
+            "g" | "gb" | "gib" => 1 << 30,
+            _ => return None,
+        };
+        number.parse::<u64>().ok()?.checked_mul(multiplier)
+    }
+
+    /// Permissions for unix sockets, parsed from the octal `unix_mode`.
+    pub fn unix_mode(&self) -> Option<u32> {
+        let mode = u32::from_str_radix(self.unix_mode.as_ref()?, 8).ok()?;
+        if mode <= 0o7777 {
+            Some(mode)
+        } else {
+            None
+        }
+    }
 }
 
 #[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct ConfigBuilder {
     html: Option<PathBuf>,
     css: Option<PathBuf>,
@@ -27,76 +185,346 @@ pub struct ConfigBuilder {
     unsafe_inline: Option<bool>,
     host: Option<String>,
     port: Option<u16>,
+    listen: Option<Vec<String>>,
+    unix_mode: Option<String>,
+    unix_owner: Option<String>,
     static_path: Option<String>,
     static_content: Option<PathBuf>,
+    static_symlinks: Option<String>,
+    static_cache_size: Option<String>,
+    cache: Option<CacheConfig>,
     mime_types: Option<PathBuf>,
+    trusted_proxies: Option<Vec<IpAddr>>,
+    defaults: Option<HashMap<String, Value>>,
+    status: Option<u16>,
+    retry_after: Option<String>,
+    schedule: Option<Vec<WindowConfig>>,
+    reload_interval: Option<u64>,
+    drain_timeout: Option<u64>,
+    health_path: Option<String>,
+    ready_path: Option<String>,
+    version_path: Option<String>,
+    metrics_path: Option<String>,
+    access_log: Option<PathBuf>,
+    access_log_format: Option<String>,
+    log_level: Option<String>,
+    tls_cert: Option<PathBuf>,
+    tls_key: Option<PathBuf>,
+    redirect_port: Option<u16>,
+    #[serde(skip)]
+    sources: BTreeMap<&'static str, Source>,
 }
 
+macro_rules! fields {
+    ($($field:ident),* $(,)?) => {
+        /// Names of every config field, as used in the config file.
+        const FIELDS: &[&str] = &[$(stringify!($field)),*];
+
+        impl ConfigBuilder {
+            /// Names of the fields that have a value.
+            fn set_fields(&self) -> Vec<&'static str> {
+                let mut set = Vec::new();
+                $(
+                    if self.$field.is_some() {
+                        set.push(stringify!($field));
+                    }
+                )*
+                set
+            }
+        }
+    };
+}
+
+fields!(
+    html,
+    css,
+    js,
+    unsafe_inline,
+    host,
+    port,
+    listen,
+    unix_mode,
+    unix_owner,
+    static_path,
+    static_content,
+    static_symlinks,
+    static_cache_size,
+    cache,
+    mime_types,
+    trusted_proxies,
+    defaults,
+    status,
+    retry_after,
+    schedule,
+    reload_interval,
+    drain_timeout,
+    health_path,
+    ready_path,
+    version_path,
+    metrics_path,
+    access_log,
+    access_log_format,
+    log_level,
+    tls_cert,
+    tls_key,
+    redirect_port,
+);
+
 impl ConfigBuilder {
-    pub fn build(&self) -> Result<Config, ()> {
-        if self.html.is_none()
-            || self.unsafe_inline.is_none()
-            || self.host.is_none()
-            || self.port.is_none()
-            || self.static_path.is_none()
-        {
-            Err(())
+    /// Build the config, or report every missing or invalid value.
+    pub fn build(&self) -> Result<Config, GenericError> {
+        let mut missing = Vec::new();
+        macro_rules! required {
+            ($field:ident) => {
+                match &self.$field {
+                    Some(value) => value.clone(),
+                    None => {
+                        missing.push(format!("{} is required", stringify!($field)));
+                        Default::default()
+                    }
+                }
+            };
+        }
+
+        let config = Config {
+            html: required!(html),
+            css: self.css.clone(),
+            js: self.js.clone(),
+            unsafe_inline: required!(unsafe_inline),
+            host: required!(host),
+            port: required!(port),
+            listen: required!(listen),
+            unix_mode: self.unix_mode.clone(),
+            unix_owner: self.unix_owner.clone(),
+            static_path: required!(static_path),
+            static_content: self.static_content.clone(),
+            static_symlinks: required!(static_symlinks),
+            static_cache_size: required!(static_cache_size),
+            cache: required!(cache),
+            mime_types: required!(mime_types),
+            trusted_proxies: required!(trusted_proxies),
+            defaults: required!(defaults),
+            status: required!(status),
+            retry_after: self.retry_after.clone(),
+            schedule: required!(schedule),
+            reload_interval: required!(reload_interval),
+            drain_timeout: required!(drain_timeout),
+            health_path: required!(health_path),
+            ready_path: required!(ready_path),
+            version_path: required!(version_path),
+            metrics_path: required!(metrics_path),
+            access_log: self.access_log.clone(),
+            access_log_format: required!(access_log_format),
+            log_level: required!(log_level),
+            tls_cert: self.tls_cert.clone(),
+            tls_key: self.tls_key.clone(),
+            redirect_port: self.redirect_port,
+            sources: self.sources.clone(),
+        };
+
+        let problems: Vec<String> = missing.into_iter().chain(config.problems()).collect();
+        if problems.is_empty() {
+            Ok(config)
         } else {
-            Ok(Config {
-                html: self.html.clone().unwrap(),
-                css: self.css.clone(),
-                js: self.js.clone(),
-                unsafe_inline: self.unsafe_inline.unwrap(),
-                host: self.host.clone().unwrap(),
-                port: self.port.unwrap(),
-                static_path: self.static_path.clone().unwrap(),
-                static_content: self.static_content.clone(),
-                mime_types: self.mime_types.clone().unwrap(),
-            })
+            Err(GenericError::ConfigError(problems))
         }
     }
 
     pub fn or_from_cmd(self, args: MainArgs) -> ConfigBuilder {
-        self.or_rather(ConfigBuilder {
-            html: args.html,
-            css: args.css,
-            js: args.js,
-            unsafe_inline: if args.unsafe_inline { Some(true) } else { None },
-            host: args.host,
-            port: args.port,
-            static_path: args.static_path,
-            static_content: args.static_content,
-            mime_types: args.mime_types,
-        })
-    }
-
-    pub fn or_from_env(self) -> ConfigBuilder {
-        self.or_rather(ConfigBuilder {
-            html: env::var("SSTATIC_HTML_PATH").ok().map(PathBuf::from),
-            css: env::var("SSTATIC_JS_PATH").ok().map(PathBuf::from),
-            js: env::var("SSTATIC_CSS_PATH").ok().map(PathBuf::from),
-            unsafe_inline: env::var("SSTATIC_UNSAFE_INLINE").ok().map(|_| true),
-            host: env::var("SSTATIC_HOST").ok().map(String::from),
-            port: env::var("SSTATIC_PORT")
-                .ok()
-                .map(|x| x.parse::<u16>().unwrap_or(3333)),
-            static_path: env::var("SSTATIC_STATIC_PATH").ok().map(String::from),
-            static_content: env::var("SSTATIC_STATIC_CONTENT").ok().map(PathBuf::from),
-            mime_types: env::var("SSTATIC_MIME_TYPES").ok().map(PathBuf::from),
-        })
+        self.or_rather(
+            ConfigBuilder {
+                html: args.html,
+                css: args.css,
+                js: args.js,
+                unsafe_inline: if args.unsafe_inline { Some(true) } else { None },
+                host: args.host,
+                port: args.port,
+                listen: if args.listen.is_empty() {
+                    None
+                } else {
+                    Some(args.listen)
+                },
+                unix_mode: args.unix_mode,
+                unix_owner: args.unix_owner,
+                static_path: args.static_path,
+                static_content: args.static_content,
+                static_symlinks: args.static_symlinks,
+                static_cache_size: args.static_cache_size,
+                cache: None,
+                mime_types: args.mime_types,
+                trusted_proxies: if args.trusted_proxy.is_empty() {
+                    None
+                } else {
+                    Some(args.trusted_proxy)
+                },
+                defaults: None,
+                status: args.status,
+                retry_after: args.retry_after,
+                schedule: None,
+                reload_interval: args.reload_interval,
+                drain_timeout: args.drain_timeout,
+                health_path: args.health_path,
+                ready_path: args.ready_path,
+                version_path: args.version_path,
+                metrics_path: args.metrics_path,
+                access_log: args.access_log,
+                access_log_format: args.access_log_format,
+                log_level: args.log_level,
+                tls_cert: args.tls_cert,
+                tls_key: args.tls_key,
+                redirect_port: args.redirect_port,
+                sources: BTreeMap::new(),
+            }
+            .sourced(Source::Cmd),
+        )
+    }
+
+    pub fn or_from_env(self) -> Result<ConfigBuilder, GenericError> {
+        self.or_from_vars(|name| env::var(name).ok())
+    }
+
+    /// Layer the `SSTATIC_*` variables that `lookup` finds on top.
+    fn or_from_vars<F: Fn(&str) -> Option<String>>(
+        self,
+        lookup: F,
+    ) -> Result<ConfigBuilder, GenericError> {
+        let sources = RefCell::new(BTreeMap::new());
+        let problems = RefCell::new(Vec::new());
+        let var = |field: &'static str, name: &'static str| {
+            let value = lookup(name);
+            if value.is_some() {
+                sources.borrow_mut().insert(field, Source::Env(name));
+            }
+            value
+        };
+        let builder = ConfigBuilder {
+            html: var("html", "SSTATIC_HTML_PATH").map(PathBuf::from),
+            css: var("css", "SSTATIC_CSS_PATH").map(PathBuf::from),
+            js: var("js", "SSTATIC_JS_PATH").map(PathBuf::from),
+            unsafe_inline: var("unsafe_inline", "SSTATIC_UNSAFE_INLINE").map(|_| true),
+            host: var("host", "SSTATIC_HOST"),
+            port: var("port", "SSTATIC_PORT")
+                .and_then(|x| parse_env("SSTATIC_PORT", &x, "a port", &problems)),
+            listen: var("listen", "SSTATIC_LISTEN").map(|x| {
+                x.split(',')
+                    .map(|address| address.trim().to_owned())
+                    .filter(|address| !address.is_empty())
+                    .collect()
+            }),
+            unix_mode: var("unix_mode", "SSTATIC_UNIX_MODE"),
+            unix_owner: var("unix_owner", "SSTATIC_UNIX_OWNER"),
+            static_path: var("static_path", "SSTATIC_STATIC_PATH"),
+            static_content: var("static_content", "SSTATIC_STATIC_CONTENT").map(PathBuf::from),
+            static_symlinks: var("static_symlinks", "SSTATIC_STATIC_SYMLINKS"),
+            static_cache_size: var("static_cache_size", "SSTATIC_STATIC_CACHE_SIZE"),
+            cache: None,
+            mime_types: var("mime_types", "SSTATIC_MIME_TYPES").map(PathBuf::from),
+            trusted_proxies: var("trusted_proxies", "SSTATIC_TRUSTED_PROXIES").map(|x| {
+                x.split(',')
+                    .map(|ip| ip.trim())
+                    .filter(|ip| !ip.is_empty())
+                    .filter_map(|ip| {
+                        parse_env("SSTATIC_TRUSTED_PROXIES", ip, "an IP address", &problems)
+                    })
+                    .collect()
+            }),
+            defaults: None,
+            status: var("status", "SSTATIC_STATUS")
+                .and_then(|x| parse_env("SSTATIC_STATUS", &x, "a status code", &problems)),
+            retry_after: var("retry_after", "SSTATIC_RETRY_AFTER"),
+            schedule: None,
+            reload_interval: var("reload_interval", "SSTATIC_RELOAD_INTERVAL")
+                .and_then(|x| parse_env("SSTATIC_RELOAD_INTERVAL", &x, "a number", &problems)),
+            drain_timeout: var("drain_timeout", "SSTATIC_DRAIN_TIMEOUT")
+                .and_then(|x| parse_env("SSTATIC_DRAIN_TIMEOUT", &x, "a number", &problems)),
+            health_path: var("health_path", "SSTATIC_HEALTH_PATH"),
+            ready_path: var("ready_path", "SSTATIC_READY_PATH"),
+            version_path: var("version_path", "SSTATIC_VERSION_PATH"),
+            metrics_path: var("metrics_path", "SSTATIC_METRICS_PATH"),
+            access_log: var("access_log", "SSTATIC_ACCESS_LOG").map(PathBuf::from),
+            access_log_format: var("access_log_format", "SSTATIC_ACCESS_LOG_FORMAT"),
+            log_level: var("log_level", "SSTATIC_LOG_LEVEL"),
+            tls_cert: var("tls_cert", "SSTATIC_TLS_CERT").map(PathBuf::from),
+            tls_key: var("tls_key", "SSTATIC_TLS_KEY").map(PathBuf::from),
+            redirect_port: var("redirect_port", "SSTATIC_REDIRECT_PORT")
+                .and_then(|x| parse_env("SSTATIC_REDIRECT_PORT", &x, "a port", &problems)),
+            sources: sources.take(),
+        };
+
+        let problems = problems.into_inner();
+        if problems.is_empty() {
+            Ok(self.or_rather(builder))
+        } else {
+            Err(GenericError::ConfigError(problems))
+        }
+    }
+
+    /// The first config file that exists in the default locations:
+    /// `./config.toml`, `$XDG_CONFIG_HOME/simplestatic/config.toml` and
+    /// `/etc/simplestatic/config.toml`.
+    pub fn discover() -> Option<PathBuf> {
+        let config_home = match env::var_os("XDG_CONFIG_HOME") {
+            Some(path) if !path.is_empty() => Some(PathBuf::from(path)),
+            _ => env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")),
+        };
+        let mut candidates = vec![PathBuf::from("config.toml")];
+        candidates.extend(config_home.map(|path| path.join("simplestatic/config.toml")));
+        candidates.push(PathBuf::from("/etc/simplestatic/config.toml"));
+        candidates.into_iter().find(|path| path.is_file())
     }
 
     pub fn or_from_file(self, path: Option<PathBuf>) -> Result<ConfigBuilder, GenericError> {
         if let Some(path) = path {
-            let otus = self.or_rather(toml::from_str(&fs::read_to_string(path)?)?);
-            dbg!(&otus);
-            Ok(otus)
+            let text = match fs::read_to_string(&path) {
+                Ok(text) => text,
+                Err(e) => Err(PathError::new(path.clone(), e.to_string()))?,
+            };
+            let table: toml::value::Table = match toml::from_str(&text) {
+                Ok(table) => table,
+                Err(e) => Err(GenericError::ConfigError(vec![format!(
+                    "{}: {}",
+                    path.display(),
+                    e
+                )]))?,
+            };
+            let unknown: Vec<String> = table
+                .keys()
+                .filter(|key| !FIELDS.contains(&key.as_str()))
+                .map(|key| match suggest(key) {
+                    Some(field) => format!(
+                        "unknown key `{}` in {}, did you mean `{}`?",
+                        key,
+                        path.display(),
+                        field
+                    ),
+                    None => format!("unknown key `{}` in {}", key, path.display()),
+                })
+                .collect();
+            if !unknown.is_empty() {
+                Err(GenericError::ConfigError(unknown))?
+            }
+            let layer: ConfigBuilder = toml::Value::Table(table).try_into()?;
+            Ok(self.or_rather(layer.sourced(Source::File(path))))
         } else {
             Ok(self)
         }
     }
 
+    /// Mark every value that has no source yet as coming from `source`.
+    fn sourced(mut self, source: Source) -> ConfigBuilder {
+        for field in self.set_fields() {
+            self.sources.entry(field).or_insert_with(|| source.clone());
+        }
+        self
+    }
+
     pub fn or_rather(&self, other: ConfigBuilder) -> ConfigBuilder {
+        let mut sources = self.sources.clone();
+        for field in other.set_fields() {
+            if let Some(source) = other.sources.get(field) {
+                sources.insert(field, source.clone());
+            }
+        }
         ConfigBuilder {
             html: other.html.or(self.html.clone()),
             css: other.css.or(self.css.clone()),
@@ -104,13 +532,81 @@ impl ConfigBuilder {
             unsafe_inline: other.unsafe_inline.or(self.unsafe_inline),
             host: other.host.or(self.host.clone()),
             port: other.port.or(self.port),
+            listen: other.listen.or(self.listen.clone()),
+            unix_mode: other.unix_mode.or(self.unix_mode.clone()),
+            unix_owner: other.unix_owner.or(self.unix_owner.clone()),
             static_path: other.static_path.or(self.static_path.clone()),
             static_content: other.static_content.or(self.static_content.clone()),
+            static_symlinks: other.static_symlinks.or(self.static_symlinks.clone()),
+            static_cache_size: other.static_cache_size.or(self.static_cache_size.clone()),
+            cache: other.cache.or(self.cache.clone()),
             mime_types: other.mime_types.or(self.mime_types.clone()),
+            trusted_proxies: other.trusted_proxies.or(self.trusted_proxies.clone()),
+            defaults: other.defaults.or(self.defaults.clone()),
+            status: other.status.or(self.status),
+            retry_after: other.retry_after.or(self.retry_after.clone()),
+            schedule: other.schedule.or(self.schedule.clone()),
+            reload_interval: other.reload_interval.or(self.reload_interval),
+            drain_timeout: other.drain_timeout.or(self.drain_timeout),
+            health_path: other.health_path.or(self.health_path.clone()),
+            ready_path: other.ready_path.or(self.ready_path.clone()),
+            version_path: other.version_path.or(self.version_path.clone()),
+            metrics_path: other.metrics_path.or(self.metrics_path.clone()),
+            access_log: other.access_log.or(self.access_log.clone()),
+            access_log_format: other.access_log_format.or(self.access_log_format.clone()),
+            log_level: other.log_level.or(self.log_level.clone()),
+            tls_cert: other.tls_cert.or(self.tls_cert.clone()),
+            tls_key: other.tls_key.or(self.tls_key.clone()),
+            redirect_port: other.redirect_port.or(self.redirect_port),
+            sources,
         }
     }
 }
 
+/// Parse `value` of the environment variable `name`, recording a problem if
+/// it is not `expected`.
+fn parse_env<T: FromStr>(
+    name: &str,
+    value: &str,
+    expected: &str,
+    problems: &RefCell<Vec<String>>,
+) -> Option<T> {
+    let parsed = value.parse().ok();
+    if parsed.is_none() {
+        problems
+            .borrow_mut()
+            .push(format!("{} {:?} is not {}", name, value, expected));
+    }
+    parsed
+}
+
+/// The field that `key` was most likely meant to be, eg. `static_path` for
+/// `static-path`.
+fn suggest(key: &str) -> Option<&'static str> {
+    let key = key.to_lowercase().replace('-', "_");
+    FIELDS
+        .iter()
+        .map(|field| (distance(&key, field), *field))
+        .filter(|(distance, _)| *distance <= 2)
+        .min()
+        .map(|(_, field)| field)
+}
+
+/// Levenshtein distance between `a` and `b`.
+fn distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, a) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b) in b.iter().enumerate() {
+            let substitution = previous[j] + if a == *b { 0 } else { 1 };
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
 impl Default for ConfigBuilder {
     fn default() -> ConfigBuilder {
         ConfigBuilder {
@@ -120,9 +616,163 @@ impl Default for ConfigBuilder {
             unsafe_inline: Some(false),
             host: Some(String::from("0.0.0.0")),
             port: Some(3333),
+            listen: Some(Vec::new()),
+            unix_mode: None,
+            unix_owner: None,
             static_path: Some(String::from("static")),
             static_content: None,
+            static_symlinks: Some(String::from("inside")),
+            static_cache_size: Some(String::from("0")),
+            cache: Some(CacheConfig::default()),
             mime_types: Some(PathBuf::from("/etc/mime.types")),
+            trusted_proxies: Some(Vec::new()),
+            defaults: Some(HashMap::new()),
+            status: Some(503),
+            retry_after: None,
+            schedule: Some(Vec::new()),
+            reload_interval: Some(0),
+            drain_timeout: Some(30),
+            health_path: Some(String::from("/_sstatic/health")),
+            ready_path: Some(String::from("/_sstatic/ready")),
+            version_path: Some(String::from("/_sstatic/version")),
+            metrics_path: Some(String::new()),
+            access_log: None,
+            access_log_format: Some(String::from("combined")),
+            log_level: Some(String::from("info")),
+            tls_cert: None,
+            tls_key: None,
+            redirect_port: None,
+            sources: BTreeMap::new(),
+        }
+        .sourced(Source::Default)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use argh::FromArgs;
+    use std::process;
+
+    fn args(args: &[&str]) -> MainArgs {
+        MainArgs::from_args(&["simplestatic"], args).unwrap()
+    }
+
+    fn env(vars: &'static [(&'static str, &'static str)]) -> impl Fn(&str) -> Option<String> {
+        move |name| {
+            vars.iter()
+                .find(|(key, _)| *key == name)
+                .map(|(_, value)| (*value).to_owned())
+        }
+    }
+
+    /// Write `text` to a config file that is removed when the returned guard
+    /// is dropped.
+    struct ConfigFile(PathBuf);
+
+    impl ConfigFile {
+        fn new(name: &str, text: &str) -> ConfigFile {
+            let path =
+                env::temp_dir().join(format!("simplestatic-{}-{}.toml", name, process::id()));
+            fs::write(&path, text).unwrap();
+            ConfigFile(path)
+        }
+    }
+
+    impl Drop for ConfigFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    #[test]
+    fn unknown_file_keys_are_rejected() {
+        let file = ConfigFile::new("unknown-keys", "static-path = \"x\"\nfoo = 1\n");
+        match ConfigBuilder::default().or_from_file(Some(file.0.clone())) {
+            Err(GenericError::ConfigError(problems)) => assert_eq!(
+                problems,
+                vec![
+                    format!("unknown key `foo` in {}", file.0.display()),
+                    format!(
+                        "unknown key `static-path` in {}, did you mean `static_path`?",
+                        file.0.display()
+                    ),
+                ]
+            ),
+            _ => panic!("expected the unknown keys to be rejected"),
+        }
+    }
+
+    #[test]
+    fn suggestions() {
+        assert_eq!(suggest("static-path"), Some("static_path"));
+        assert_eq!(suggest("STATUS"), Some("status"));
+        assert_eq!(suggest("prot"), Some("port"));
+        assert_eq!(suggest("something_else"), None);
+        assert_eq!(distance("kitten", "sitting"), 3);
+        assert_eq!(distance("", "abc"), 3);
+    }
+
+    #[test]
+    fn layers_override_each_other() {
+        let file = ConfigFile::new("layers", "host = \"127.0.0.2\"\nport = 5555\n");
+        let layered = |vars, cmd: &[&str]| {
+            ConfigBuilder::default()
+                .or_from_vars(env(vars))
+                .unwrap()
+                .or_from_file(Some(file.0.clone()))
+                .unwrap()
+                .or_from_cmd(args(cmd))
+                .build()
+                .unwrap()
+        };
+
+        let config = layered(
+            &[("SSTATIC_PORT", "4444"), ("SSTATIC_STATUS", "500")],
+            &["--port", "6666"],
+        );
+        assert_eq!(config.drain_timeout, 30);
+        assert_eq!(config.sources["drain_timeout"], Source::Default);
+        assert_eq!(config.status, 500);
+        assert_eq!(config.sources["status"], Source::Env("SSTATIC_STATUS"));
+        assert_eq!(config.host, "127.0.0.2");
+        assert_eq!(config.sources["host"], Source::File(file.0.clone()));
+        assert_eq!(config.port, 6666);
+        assert_eq!(config.sources["port"], Source::Cmd);
+
+        let config = layered(&[("SSTATIC_HOST", "127.0.0.3")], &[]);
+        assert_eq!(config.host, "127.0.0.2");
+        assert_eq!(config.port, 5555);
+        assert_eq!(config.sources["port"], Source::File(file.0.clone()));
+    }
+
+    #[test]
+    fn env_overrides_defaults() {
+        let config = ConfigBuilder::default()
+            .or_from_vars(env(&[("SSTATIC_PORT", "4444")]))
+            .unwrap()
+            .build()
+            .unwrap();
+        assert_eq!(config.port, 4444);
+        assert_eq!(config.sources["port"], Source::Env("SSTATIC_PORT"));
+        assert_eq!(config.sources["host"], Source::Default);
+    }
+
+    #[test]
+    fn invalid_env_values_are_reported() {
+        let vars = &[
+            ("SSTATIC_PORT", "x"),
+            ("SSTATIC_TRUSTED_PROXIES", "::1, nope"),
+        ];
+        match ConfigBuilder::default().or_from_vars(env(vars)) {
+            Err(GenericError::ConfigError(problems)) => assert_eq!(
+                problems,
+                vec![
+                    "SSTATIC_PORT \"x\" is not a port",
+                    "SSTATIC_TRUSTED_PROXIES \"nope\" is not an IP address",
+                ]
+            ),
+            _ => panic!("expected the invalid values to be reported"),
         }
     }
 }
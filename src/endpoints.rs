@@ -0,0 +1,232 @@
+use crate::config::Config;
+use crate::metrics::Metrics;
+use crate::server::Routes;
+use crate::site::SiteHandle;
+use serde_derive::Serialize;
+use std::sync::Arc;
+use warp::http::StatusCode;
+use warp::path::FullPath;
+use warp::reply::Reply;
+use warp::{Filter, Rejection};
+
+#[derive(Serialize)]
+struct Health {
+    status: &'static str,
+}
+
+#[derive(Serialize)]
+struct Readiness {
+    /// Whether a site is loaded and can be served. A failed reload leaves the
+    /// previous site in place, so it does not make the server unready.
+    ready: bool,
+    /// The error from the last reload, if it failed.
+    error: Option<String>,
+    /// Whether the static content file or directory exists, if one is configured.
+    static_content: Option<bool>,
+}
+
+#[derive(Serialize)]
+struct Version {
+    name: &'static str,
+    version: &'static str,
+}
+
+/// Health, readiness, version and metrics endpoints, or None if they are
+/// all disabled. These are served before anything else.
+pub fn routes(config: &Config, handle: SiteHandle, metrics: Arc<Metrics>) -> Option<Routes> {
+    let mut routes: Vec<Routes> = Vec::new();
+
+    if !config.health_path.is_empty() {
+        routes.push(
+            metrics.count(
+                "health",
+                exact(config.health_path.clone())
+                    .map(|| boxed(warp::reply::json(&Health { status: "ok" })))
+                    .boxed(),
+            ),
+        );
+    }
+    if !config.ready_path.is_empty() {
+        let handle = handle.clone();
+        routes.push(
+            metrics.count(
+                "ready",
+                exact(config.ready_path.clone())
+                    .map(move || {
+                        let readiness = readiness(&handle);
+                        let status = if readiness.ready {
+                            StatusCode::OK
+                        } else {
+                            StatusCode::SERVICE_UNAVAILABLE
+                        };
+                        boxed(warp::reply::with_status(
+                            warp::reply::json(&readiness),
+                            status,
+                        ))
+                    })
+                    .boxed(),
+            ),
+        );
+    }
+    if !config.version_path.is_empty() {
+        routes.push(
+            metrics.count(
+                "version",
+                exact(config.version_path.clone())
+                    .map(|| {
+                        boxed(warp::reply::json(&Version {
+                            name: env!("CARGO_PKG_NAME"),
+                            version: env!("CARGO_PKG_VERSION"),
+                        }))
+                    })
+                    .boxed(),
+            ),
+        );
+    }
+    if !config.metrics_path.is_empty() {
+        routes.push(
+            exact(config.metrics_path.clone())
+                .map(move || {
+                    boxed(warp::reply::with_header(
+                        metrics.render(&handle.current()),
+                        "Content-Type",
+                        "text/plain; version=0.0.4",
+                    ))
+                })
+                .boxed(),
+        );
+    }
+
+    routes
+        .into_iter()
+        .reduce(|first, second| first.or(second).unify().boxed())
+}
+
+fn readiness(handle: &SiteHandle) -> Readiness {
+    let site = handle.current();
+    let error = handle.error();
+    let static_content = site
+        .content_path
+        .as_ref()
+        .map(|content_path| content_path.exists());
+    Readiness {
+        ready: static_content != Some(false),
+        error,
+        static_content,
+    }
+}
+
+/// Matches GET and HEAD requests to exactly `path`.
+fn exact(path: String) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::get()
+        .or(warp::head())
+        .unify()
+        .and(warp::path::full())
+        .and_then(move |full: FullPath| {
+            let matches = full.as_str() == path;
+            async move {
+                if matches {
+                    Ok(())
+                } else {
+                    Err(warp::reject::not_found())
+                }
+            }
+        })
+        .untuple_one()
+}
+
+fn boxed(reply: impl Reply + 'static) -> Box<dyn Reply> {
+    Box::new(reply)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ConfigBuilder;
+    use crate::files::tests::TempDir;
+    use crate::schedule::SystemClock;
+    use crate::site::Site;
+    use std::fs;
+
+    fn config(dir: &TempDir) -> Config {
+        let mut config = ConfigBuilder::default().build().unwrap();
+        config.html = dir.write("index.html", "<p>Down</p>");
+        config.static_content = Some(dir.write("static/a.txt", "a").parent().unwrap().into());
+        config
+    }
+
+    async fn get(routes: &Routes, path: &str) -> (StatusCode, String) {
+        let response = warp::test::request().path(path).reply(routes).await;
+        let body = String::from_utf8(response.body().to_vec()).unwrap();
+        (response.status(), body)
+    }
+
+    #[tokio::test]
+    async fn health_and_readiness() {
+        let dir = TempDir::new("endpoints");
+        let config = config(&dir);
+        let handle = SiteHandle::new(Site::load(config.clone(), Arc::new(SystemClock)).unwrap());
+        let routes = routes(&config, handle.clone(), Arc::new(Metrics::default())).unwrap();
+
+        assert_eq!(
+            get(&routes, "/_sstatic/health").await,
+            (StatusCode::OK, r#"{"status":"ok"}"#.to_owned())
+        );
+        assert_eq!(
+            get(&routes, "/_sstatic/ready").await,
+            (
+                StatusCode::OK,
+                r#"{"ready":true,"error":null,"static_content":true}"#.to_owned()
+            )
+        );
+
+        // The previous site is still served after a failed reload
+        handle.fail("index.html: line 1, column 3".to_owned());
+        assert_eq!(
+            get(&routes, "/_sstatic/ready").await,
+            (
+                StatusCode::OK,
+                r#"{"ready":true,"error":"index.html: line 1, column 3","static_content":true}"#
+                    .to_owned()
+            )
+        );
+
+        fs::remove_dir_all(dir.0.join("static")).unwrap();
+        assert_eq!(
+            get(&routes, "/_sstatic/ready").await,
+            (
+                StatusCode::SERVICE_UNAVAILABLE,
+                r#"{"ready":false,"error":"index.html: line 1, column 3","static_content":false}"#
+                    .to_owned()
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn single_file_content_is_ready() {
+        let dir = TempDir::new("endpoints-single");
+        let mut config = config(&dir);
+        config.static_content = Some(dir.write("file.txt", "file"));
+        let handle = SiteHandle::new(Site::load(config.clone(), Arc::new(SystemClock)).unwrap());
+        let routes = routes(&config, handle, Arc::new(Metrics::default())).unwrap();
+        assert_eq!(get(&routes, "/_sstatic/ready").await.0, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn disabled_endpoints() {
+        let dir = TempDir::new("endpoints-disabled");
+        let mut config = config(&dir);
+        config.ready_path = String::new();
+        config.version_path = String::new();
+        let handle = SiteHandle::new(Site::load(config.clone(), Arc::new(SystemClock)).unwrap());
+        let routes = routes(&config, handle.clone(), Arc::new(Metrics::default())).unwrap();
+        assert_eq!(
+            get(&routes, "/_sstatic/ready").await.0,
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(get(&routes, "/_sstatic/health").await.0, StatusCode::OK);
+
+        config.health_path = String::new();
+        assert!(super::routes(&config, handle, Arc::new(Metrics::default())).is_none());
+    }
+}
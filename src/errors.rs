@@ -3,14 +3,34 @@ use std::io;
 use std::path::PathBuf;
 
 #[derive(Debug)]
+#[allow(clippy::enum_variant_names)]
 pub enum GenericError {
     PathError(PathError),
+    SyntaxError(SyntaxError),
     IOError(io::Error),
     RegexError(regex::Error),
     TOMLError(toml::de::Error),
+    /// Every missing or invalid config value.
+    ConfigError(Vec<String>),
+    /// Failure to resolve or listen on an address.
+    BindError(String),
     StrError(String),
 }
 
+impl GenericError {
+    /// Process exit code for the error, following sysexits.h.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            GenericError::SyntaxError(_) => 65,
+            GenericError::PathError(_) | GenericError::IOError(_) => 66,
+            GenericError::BindError(_) => 69,
+            GenericError::RegexError(_) => 70,
+            GenericError::TOMLError(_) | GenericError::ConfigError(_) => 78,
+            GenericError::StrError(_) => 1,
+        }
+    }
+}
+
 impl From<io::Error> for GenericError {
     fn from(e: io::Error) -> GenericError {
         GenericError::IOError(e)
@@ -23,6 +43,12 @@ impl From<PathError> for GenericError {
     }
 }
 
+impl From<SyntaxError> for GenericError {
+    fn from(e: SyntaxError) -> GenericError {
+        GenericError::SyntaxError(e)
+    }
+}
+
 impl From<regex::Error> for GenericError {
     fn from(e: regex::Error) -> GenericError {
         GenericError::RegexError(e)
@@ -35,6 +61,12 @@ impl From<toml::de::Error> for GenericError {
     }
 }
 
+impl From<String> for GenericError {
+    fn from(e: String) -> GenericError {
+        GenericError::StrError(e)
+    }
+}
+
 impl From<&str> for GenericError {
     fn from(e: &str) -> GenericError {
         GenericError::StrError(e.to_owned())
@@ -47,6 +79,9 @@ impl fmt::Display for GenericError {
             GenericError::PathError(e) => {
                 format!("Error: {}", e)
             }
+            GenericError::SyntaxError(e) => {
+                format!("Error in template: {}", e)
+            }
             GenericError::IOError(e) => format!("Error: {}", e),
             GenericError::RegexError(e) => {
                 format!("Error: {}", e)
@@ -57,6 +92,10 @@ impl fmt::Display for GenericError {
             GenericError::TOMLError(e) => {
                 format!("Error parsing toml: {}", e)
             }
+            GenericError::ConfigError(problems) => {
+                format!("Invalid configuration:\n  {}", problems.join("\n  "))
+            }
+            GenericError::BindError(e) => format!("Unable to listen: {}", e),
         };
         write!(f, "{}", text)
     }
@@ -82,3 +121,43 @@ impl fmt::Display for PathError {
         write!(f, "Error with path {:?}: {}", self.path, self.text)
     }
 }
+
+#[derive(Debug)]
+pub struct SyntaxError {
+    /// The file the error is in, if known.
+    pub path: Option<PathBuf>,
+    /// Byte offset of the error in the parsed text.
+    pub offset: usize,
+    pub line: usize,
+    pub column: usize,
+    pub text: String,
+}
+
+impl SyntaxError {
+    /// Create an error pointing at the byte `offset` of `source`.
+    pub fn at<T: Into<String>>(source: &str, offset: usize, text: T) -> Self {
+        let before = &source[..offset];
+        let line = before.matches('\n').count() + 1;
+        let line_start = before.rfind('\n').map(|i| i + 1).unwrap_or(0);
+        SyntaxError {
+            path: None,
+            offset,
+            line,
+            column: before[line_start..].chars().count() + 1,
+            text: text.into(),
+        }
+    }
+}
+
+impl fmt::Display for SyntaxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(path) = &self.path {
+            write!(f, "{}: ", path.display())?;
+        }
+        write!(
+            f,
+            "line {}, column {}: {}",
+            self.line, self.column, self.text
+        )
+    }
+}
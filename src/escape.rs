@@ -0,0 +1,175 @@
+use std::fmt::Write;
+
+/// How a value must be escaped depending on where its placeholder sits in
+/// the html document.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Escape {
+    /// Regular html text between tags.
+    Html,
+    /// Inside a tag, ie. as an attribute value.
+    Attribute,
+    /// At the start of a URL attribute like `href`, where a `javascript:`
+    /// URL would run.
+    Url,
+    /// Inside a `<script>` block.
+    Script,
+    /// Inside a `<style>` block.
+    Style,
+    /// Explicitly requested with `{{ raw ... }}`, nothing is escaped.
+    Raw,
+}
+
+impl Escape {
+    pub fn write(&self, value: &str, out: &mut String) {
+        match self {
+            Escape::Html => {
+                for c in value.chars() {
+                    match c {
+                        '&' => out.push_str("&amp;"),
+                        '<' => out.push_str("&lt;"),
+                        '>' => out.push_str("&gt;"),
+                        '"' => out.push_str("&quot;"),
+                        '\'' => out.push_str("&#x27;"),
+                        c => out.push(c),
+                    }
+                }
+            }
+            Escape::Url if !safe_url(value) => Escape::Attribute.write("about:invalid", out),
+            Escape::Attribute | Escape::Url => {
+                // Escape everything that is not alphanumeric so that even
+                // unquoted attributes can not be broken out of.
+                for c in value.chars() {
+                    if c.is_ascii_alphanumeric() || !c.is_ascii() {
+                        out.push(c);
+                    } else {
+                        let _ = write!(out, "&#x{:02X};", c as u32);
+                    }
+                }
+            }
+            Escape::Script => {
+                for c in value.chars() {
+                    if c.is_ascii_alphanumeric() || c == ' ' {
+                        out.push(c);
+                    } else {
+                        let mut buf = [0; 2];
+                        for unit in c.encode_utf16(&mut buf) {
+                            let _ = write!(out, "\\u{:04X}", unit);
+                        }
+                    }
+                }
+            }
+            Escape::Style => {
+                for c in value.chars() {
+                    if c.is_ascii_alphanumeric() || c == ' ' {
+                        out.push(c);
+                    } else {
+                        let _ = write!(out, "\\{:06X}", c as u32);
+                    }
+                }
+            }
+            Escape::Raw => out.push_str(value),
+        }
+    }
+}
+
+/// Schemes that are allowed at the start of a URL attribute.
+const SAFE_SCHEMES: &[&str] = &["http", "https", "mailto", "tel"];
+
+/// Whether `url` is relative or has one of `SAFE_SCHEMES`.
+fn safe_url(url: &str) -> bool {
+    let scheme_end = match url.find(|c| matches!(c, ':' | '/' | '?' | '#')) {
+        Some(i) if url[i..].starts_with(':') => i,
+        _ => return true,
+    };
+    // Browsers ignore whitespace and control characters in the scheme
+    let scheme: String = url[..scheme_end]
+        .chars()
+        .filter(|c| !c.is_ascii_whitespace() && !c.is_ascii_control())
+        .collect::<String>()
+        .to_ascii_lowercase();
+    SAFE_SCHEMES.contains(&scheme.as_str())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Escape;
+
+    fn escape(escape: Escape, value: &str) -> String {
+        let mut out = String::new();
+        escape.write(value, &mut out);
+        out
+    }
+
+    #[test]
+    fn html_escapes_markup() {
+        assert_eq!(
+            escape(Escape::Html, r#"<a href="x">'&'</a>"#),
+            "&lt;a href=&quot;x&quot;&gt;&#x27;&amp;&#x27;&lt;/a&gt;"
+        );
+        assert_eq!(escape(Escape::Html, "plain text ä"), "plain text ä");
+    }
+
+    #[test]
+    fn attribute_escapes_everything_but_alphanumerics() {
+        assert_eq!(
+            escape(Escape::Attribute, "a b\"c'd=e>f"),
+            "a&#x20;b&#x22;c&#x27;d&#x3D;e&#x3E;f"
+        );
+        assert_eq!(escape(Escape::Attribute, "ä"), "ä");
+    }
+
+    #[test]
+    fn urls_with_unsafe_schemes_are_replaced() {
+        assert_eq!(
+            escape(Escape::Url, "https://a.b/c?d"),
+            "https&#x3A;&#x2F;&#x2F;a&#x2E;b&#x2F;c&#x3F;d"
+        );
+        assert_eq!(escape(Escape::Url, "MAILTO:x"), "MAILTO&#x3A;x");
+        assert_eq!(escape(Escape::Url, "/path:x"), "&#x2F;path&#x3A;x");
+        assert_eq!(
+            escape(Escape::Url, "page?a=b:c"),
+            "page&#x3F;a&#x3D;b&#x3A;c"
+        );
+        assert_eq!(escape(Escape::Url, "#top"), "&#x23;top");
+        for unsafe_url in &[
+            "javascript:alert(1)",
+            "JavaScript:alert(1)",
+            " java\tscript:alert(1)",
+            "data:text/html,x",
+            "vbscript:x",
+            ":x",
+        ] {
+            assert_eq!(
+                escape(Escape::Url, unsafe_url),
+                "about&#x3A;invalid",
+                "{}",
+                unsafe_url
+            );
+        }
+    }
+
+    #[test]
+    fn script_can_not_close_the_block() {
+        assert_eq!(
+            escape(Escape::Script, "</script>"),
+            "\\u003C\\u002Fscript\\u003E"
+        );
+        assert_eq!(escape(Escape::Script, "\"a b\""), "\\u0022a b\\u0022");
+        // Characters outside the basic plane become a surrogate pair
+        assert_eq!(escape(Escape::Script, "😀"), "\\uD83D\\uDE00");
+    }
+
+    #[test]
+    fn style_can_not_close_the_block() {
+        assert_eq!(
+            escape(Escape::Style, "</style>"),
+            "\\00003C\\00002Fstyle\\00003E"
+        );
+        assert_eq!(escape(Escape::Style, "red;}"), "red\\00003B\\00007D");
+    }
+
+    #[test]
+    fn raw_is_not_escaped() {
+        assert_eq!(escape(Escape::Raw, "<b>\"x\"</b>"), "<b>\"x\"</b>");
+    }
+}
@@ -1,14 +1,23 @@
+use crate::cache_control::CachePolicy;
+use crate::conditional::Validators;
 use crate::config::Config;
-use crate::errors::{GenericError, PathError};
+use crate::errors::{GenericError, PathError, SyntaxError};
+use crate::request::Request;
+use log::warn;
+use percent_encoding::percent_decode_str;
+use regex::Regex;
 use std::collections::HashMap;
 use std::fs;
 use std::io;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use warp::http::header::{HeaderValue, CACHE_CONTROL, CONTENT_TYPE};
 use warp::http::StatusCode;
-use warp::reply::Reply;
+use warp::hyper::Body;
+use warp::reply::{Reply, Response};
 
-static DEFAULT_HTML: &'static str = include_str!("default.html");
-static DEFAULT_MIMETYPES: &'static str = include_str!("mime.types");
+static DEFAULT_HTML: &str = include_str!("default.html");
+static DEFAULT_MIMETYPES: &str = include_str!("mime.types");
 
 #[derive(Debug, Clone)]
 pub struct Mimetypes {
@@ -31,7 +40,7 @@ impl Mimetypes {
             }
             let mut parts = row.split_whitespace();
             let mimetype = parts.next();
-            while let Some(ext) = parts.next() {
+            for ext in parts {
                 map.insert(ext.to_owned(), mimetype.unwrap().to_owned());
             }
         }
@@ -39,7 +48,15 @@ impl Mimetypes {
     }
 
     fn get<T: Into<String>>(&self, key: T) -> Option<String> {
-        self.map.get(&key.into()).map(|x| x.clone())
+        self.map.get(&key.into()).cloned()
+    }
+
+    /// The mime type of a file, by its extension.
+    pub fn for_path(&self, path: &Path) -> String {
+        path.extension()
+            .and_then(|x| x.to_str())
+            .and_then(|x| self.get(x))
+            .unwrap_or_else(|| String::from("text/plain"))
     }
 }
 
@@ -49,99 +66,461 @@ impl Default for Mimetypes {
     }
 }
 
-pub fn serve_file(mime_types: &Mimetypes, content_path: &PathBuf, path: String) -> Box<dyn Reply> {
-    if let Ok(metadata) = fs::metadata(content_path) {
-        if metadata.is_dir() {
-            let new_path = content_path.join(path);
+/// How symlinks under `static_content` are treated.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Symlinks {
+    /// Follow every link, even out of `static_content`
+    Follow,
+    /// Serve nothing through a link
+    Deny,
+    /// Follow links that stay inside `static_content`
+    Inside,
+}
 
-            if let Ok(metadata) = fs::metadata(&new_path) {
-                if metadata.is_dir() {
-                    simple_404()
-                } else {
-                    get_file(mime_types, &new_path)
-                }
-            } else {
-                simple_404()
-            }
-        } else {
-            get_file(mime_types, content_path)
+impl FromStr for Symlinks {
+    type Err = GenericError;
+
+    fn from_str(text: &str) -> Result<Symlinks, GenericError> {
+        match text.to_lowercase().as_str() {
+            "follow" => Ok(Symlinks::Follow),
+            "deny" => Ok(Symlinks::Deny),
+            "inside" => Ok(Symlinks::Inside),
+            _ => Err(format!(
+                "Unknown static_symlinks policy {:?}, expected follow, deny or inside",
+                text
+            ))?,
         }
-    } else {
-        simple_404()
     }
 }
 
-fn simple_404() -> Box<dyn Reply> {
+/// Serve the file that `tail`, the still percent-encoded rest of the request
+/// path, points to under `content_path`, or None if there is no such file.
+/// A single file is served for every path.
+pub fn serve_file(
+    mime_types: &Mimetypes,
+    content_path: &Path,
+    tail: &str,
+    symlinks: Symlinks,
+    cache_control: &CachePolicy,
+    request: &Request,
+) -> Option<Box<dyn Reply>> {
+    if content_path.is_file() {
+        let name = content_path.file_name()?.to_string_lossy();
+        return get_file(mime_types, content_path, &name, cache_control, request);
+    }
+    match resolve(content_path, tail, symlinks) {
+        Some(path) if path.is_file() => {
+            let key = segments(tail)?.join("/");
+            get_file(mime_types, &path, &key, cache_control, request)
+        }
+        _ => None,
+    }
+}
+
+/// The decoded segments of `tail`, the still percent-encoded rest of the
+/// request path. None if a segment would go up a directory or contains a
+/// separator.
+pub fn segments(tail: &str) -> Option<Vec<String>> {
+    let mut segments = Vec::new();
+    for segment in tail.split('/') {
+        // Decoding happens per segment, so an encoded slash can not add
+        // segments, and an encoded dot dot is caught like a plain one
+        let segment = percent_decode_str(segment).decode_utf8().ok()?;
+        match &*segment {
+            "" | "." => continue,
+            ".." => return None,
+            segment if segment.contains(&['/', '\\', '\0'][..]) => return None,
+            segment => segments.push(segment.to_owned()),
+        }
+    }
+    Some(segments)
+}
+
+/// The canonical path of `tail` under `root`, which must be canonical. None
+/// if the path does not exist, would leave `root`, or goes through a
+/// symlink that `symlinks` does not allow.
+pub fn resolve(root: &Path, tail: &str, symlinks: Symlinks) -> Option<PathBuf> {
+    let mut path = root.to_path_buf();
+    for segment in segments(tail)? {
+        path.push(segment);
+        if symlinks == Symlinks::Deny && fs::symlink_metadata(&path).ok()?.file_type().is_symlink()
+        {
+            return None;
+        }
+    }
+
+    let canonical = path.canonicalize().ok()?;
+    if symlinks != Symlinks::Follow && !canonical.starts_with(root) {
+        return None;
+    }
+    Some(canonical)
+}
+
+pub fn simple_404() -> Box<dyn Reply> {
     Box::new(warp::reply::with_status(
         String::from("404"),
         StatusCode::NOT_FOUND,
     ))
 }
 
-fn get_file(mime_types: &Mimetypes, path: &PathBuf) -> Box<dyn Reply> {
-    if let Ok(content) = fs::read(path) {
-        let mime = path
-            .extension()
-            .map(|x| x.to_str())
-            .flatten()
-            .map(move |x| mime_types.get(x))
-            .flatten()
-            .unwrap_or(String::from("text/plain"));
-        Box::new(warp::reply::with_header(content, "Content-Type", mime))
+/// Serve the file at `path`, which is served for `key`, the decoded request
+/// path under `static_path`.
+fn get_file(
+    mime_types: &Mimetypes,
+    path: &Path,
+    key: &str,
+    cache_control: &CachePolicy,
+    request: &Request,
+) -> Option<Box<dyn Reply>> {
+    let content = fs::read(path).ok()?;
+    let modified = fs::metadata(path).and_then(|x| x.modified()).ok();
+    let mime = mime_types.for_path(path);
+    let cache_control = cache_control.for_file(key, &mime);
+    let validators = Validators::new(&content, modified);
+    let mut response = if validators.fresh(request) {
+        validators.not_modified()
     } else {
-        simple_404()
+        let mut response = Response::new(Body::from(content));
+        let headers = response.headers_mut();
+        if let Ok(mime) = HeaderValue::from_str(&mime) {
+            headers.insert(CONTENT_TYPE, mime);
+        }
+        validators.apply(headers);
+        response
+    };
+    if let Some(value) = cache_control {
+        response.headers_mut().insert(CACHE_CONTROL, value.clone());
+    }
+    Some(Box::new(response))
+}
+
+/// Contents of the html file and the embedded css and js files.
+pub struct Files {
+    pub html: String,
+    pub css: Option<Vec<String>>,
+    pub js: Option<Vec<String>>,
+    /// Every file the contents were read from.
+    pub paths: Vec<PathBuf>,
+    /// Where each part of `html` came from.
+    pub sources: Sources,
+}
+
+/// The files that make up the html text once includes are resolved, to
+/// point errors at the file and position they come from.
+#[derive(Default)]
+pub struct Sources {
+    /// Path and text of every file.
+    files: Vec<(PathBuf, String)>,
+    /// Where each part starts in the resolved text, the index of the file it
+    /// came from and where it starts in that file, in order.
+    parts: Vec<(usize, usize, usize)>,
+}
+
+impl Sources {
+    /// Point an error in the resolved text at the file it came from.
+    pub fn locate(&self, error: SyntaxError) -> SyntaxError {
+        let part = self
+            .parts
+            .iter()
+            .rev()
+            .find(|(start, _, _)| *start <= error.offset);
+        match part {
+            Some(&(start, file, file_start)) => {
+                let (path, text) = &self.files[file];
+                let mut located =
+                    SyntaxError::at(text, file_start + error.offset - start, error.text);
+                located.path = Some(path.clone());
+                located
+            }
+            None => error,
+        }
     }
 }
 
-pub fn get_files(
-    config: &Config,
-) -> Result<(String, Option<Vec<String>>, Option<Vec<String>>), GenericError> {
-    if let Ok(metadata) = fs::metadata(&config.html) {
+pub fn get_files(config: &Config, html: &Path) -> Result<Files, GenericError> {
+    if let Ok(metadata) = fs::metadata(html) {
         if metadata.is_dir() {
             Err(PathError::new(
-                config.html.clone(),
+                html.to_path_buf(),
                 "html path must not be a directory",
             ))?
         }
     };
-    let html_file = match fs::read_to_string(&config.html) {
-        Ok(file) => file,
+    let mut sources = Sources::default();
+    let html_file = match fs::read_to_string(html) {
+        Ok(file) => {
+            let mut resolved = String::with_capacity(file.len());
+            resolve_includes(&file, html, &mut Vec::new(), &mut sources, &mut resolved)?;
+            resolved
+        }
         Err(e) => {
-            println!("No html file found: {}. Using default html file.", e);
+            warn!("No html file found: {}. Using default html file.", e);
             String::from(DEFAULT_HTML)
         }
     };
 
     let css_files = if let Some(css_path) = &config.css {
-        Some(handle_dir_or_file(&css_path)?)
+        Some(handle_dir_or_file(css_path)?)
     } else {
         None
     };
     let js_files = if let Some(js_path) = &config.js {
-        Some(handle_dir_or_file(&js_path)?)
+        Some(handle_dir_or_file(js_path)?)
     } else {
         None
     };
 
-    Ok((html_file, css_files, js_files))
+    let mut paths = vec![html.to_path_buf()];
+    paths.extend(sources.files.iter().skip(1).map(|(path, _)| path.clone()));
+    paths.extend(config.css.iter().cloned());
+    paths.extend(config.js.iter().cloned());
+
+    Ok(Files {
+        html: html_file,
+        css: css_files,
+        js: js_files,
+        paths,
+        sources,
+    })
 }
 
-pub fn handle_dir_or_file(path: &PathBuf) -> Result<Vec<String>, io::Error> {
-    let metadata = fs::metadata(&path)?;
+/// Write `text` to `resolved`, replacing every `{% include "file.html" %}`
+/// with the contents of the file, relative to the file that includes it.
+/// `stack` holds the files currently being included, to detect cycles, and
+/// every file is added to `sources`.
+fn resolve_includes(
+    text: &str,
+    path: &Path,
+    stack: &mut Vec<PathBuf>,
+    sources: &mut Sources,
+    resolved: &mut String,
+) -> Result<(), GenericError> {
+    let canonical = path.canonicalize()?;
+    if stack.contains(&canonical) {
+        let cycle: Vec<String> = stack
+            .iter()
+            .skip_while(|x| **x != canonical)
+            .chain(Some(&canonical))
+            .map(|x| x.display().to_string())
+            .collect();
+        Err(PathError::new(
+            canonical.clone(),
+            format!("include cycle: {}", cycle.join(" -> ")),
+        ))?
+    }
+
+    let regex = Regex::new(r#"\{%\s*(?i:include)\s+"(?P<path>[^"]*)"\s*%\}"#)?;
+    let file = sources.files.len();
+    sources.files.push((path.to_path_buf(), text.to_owned()));
+
+    stack.push(canonical);
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    let mut last = 0;
+    for caps in regex.captures_iter(text) {
+        let whole = caps.get(0).unwrap();
+        let include_path = dir.join(&caps["path"]);
+        let content = match fs::read_to_string(&include_path) {
+            Ok(content) => content,
+            Err(e) => Err(PathError::new(
+                include_path.clone(),
+                format!("unable to include file: {}", e),
+            ))?,
+        };
+        sources.parts.push((resolved.len(), file, last));
+        resolved.push_str(&text[last..whole.start()]);
+        resolve_includes(&content, &include_path, stack, sources, resolved)?;
+        last = whole.end();
+    }
+    sources.parts.push((resolved.len(), file, last));
+    resolved.push_str(&text[last..]);
+    stack.pop();
+
+    Ok(())
+}
+
+pub fn handle_dir_or_file(path: &PathBuf) -> Result<Vec<String>, GenericError> {
+    let path_error = |e: io::Error| PathError::new(path.clone(), e.to_string());
+    let metadata = fs::metadata(path).map_err(path_error)?;
     let mut list = Vec::new();
 
     if metadata.is_dir() {
-        for entry in fs::read_dir(path)? {
-            let entry_path = entry?.path();
+        for entry in fs::read_dir(path).map_err(path_error)? {
+            let entry_path = entry.map_err(path_error)?.path();
             if entry_path.is_dir() {
-                list.extend(handle_dir_or_file(&entry_path)?.drain(..));
+                list.append(&mut handle_dir_or_file(&entry_path)?);
             } else {
-                list.push(fs::read_to_string(&entry_path)?);
+                match fs::read_to_string(&entry_path) {
+                    Ok(content) => list.push(content),
+                    Err(e) => Err(PathError::new(entry_path, e.to_string()))?,
+                }
             }
         }
     } else {
-        list.push(fs::read_to_string(path)?);
+        list.push(fs::read_to_string(path).map_err(path_error)?);
     }
 
     Ok(list)
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::parser::Parser;
+    use std::env;
+    use std::os::unix::fs::symlink;
+    use std::process;
+
+    /// A directory under the system temp dir, removed when dropped.
+    pub(crate) struct TempDir(pub(crate) PathBuf);
+
+    impl TempDir {
+        pub(crate) fn new(name: &str) -> TempDir {
+            let path = env::temp_dir().join(format!("simplestatic-{}-{}", name, process::id()));
+            let _ = fs::remove_dir_all(&path);
+            fs::create_dir_all(&path).unwrap();
+            TempDir(path.canonicalize().unwrap())
+        }
+
+        pub(crate) fn write(&self, path: &str, content: &str) -> PathBuf {
+            let path = self.0.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, content).unwrap();
+            path
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn resolve_html(path: &Path) -> Result<(String, Sources), GenericError> {
+        let text = fs::read_to_string(path).unwrap();
+        let mut sources = Sources::default();
+        let mut resolved = String::new();
+        resolve_includes(&text, path, &mut Vec::new(), &mut sources, &mut resolved)?;
+        Ok((resolved, sources))
+    }
+
+    #[test]
+    fn includes_are_resolved_relative_to_the_including_file() {
+        let dir = TempDir::new("includes");
+        let html = dir.write("index.html", "a{% include \"parts/b.html\" %}d");
+        dir.write("parts/b.html", "b{%INCLUDE \"c.html\"%}");
+        dir.write("parts/c.html", "c");
+        let (resolved, _) = resolve_html(&html).unwrap();
+        assert_eq!(resolved, "abcd");
+    }
+
+    #[test]
+    fn include_cycles_are_errors() {
+        let dir = TempDir::new("include-cycle");
+        let html = dir.write("index.html", "{% include \"a.html\" %}");
+        dir.write("a.html", "{% include \"index.html\" %}");
+        match resolve_html(&html) {
+            Err(GenericError::PathError(e)) => assert!(e.text.contains("include cycle")),
+            _ => panic!("expected an include cycle"),
+        }
+    }
+
+    #[test]
+    fn errors_point_at_the_included_file() {
+        let dir = TempDir::new("include-errors");
+        let html = dir.write("index.html", "");
+        let footer = dir.write("footer.html", "<footer>\nab {% if %}\n</footer>\n");
+        let locate = |text: &str| {
+            fs::write(&html, text).unwrap();
+            let (resolved, sources) = resolve_html(&html).unwrap();
+            let error = sources.locate(Parser::parse(&resolved).unwrap_err());
+            (error.path.unwrap(), error.line, error.column)
+        };
+        assert_eq!(
+            locate("<html>\n<body>\n{% include \"footer.html\" %}\n"),
+            (footer.clone(), 2, 4)
+        );
+        fs::write(&footer, "<footer>\n</footer>\n").unwrap();
+        assert_eq!(
+            locate("<html>\n{% include \"footer.html\" %}\n\n  {{ host\n"),
+            (html.clone(), 4, 3)
+        );
+        assert_eq!(
+            locate("{% include \"footer.html\" %}{% endif %}"),
+            (html.clone(), 1, 28)
+        );
+    }
+
+    #[test]
+    fn segments_reject_traversal() {
+        assert_eq!(segments("a/./b//c").unwrap(), vec!["a", "b", "c"]);
+        assert_eq!(segments("%C3%A9t%C3%A9").unwrap(), vec!["été"]);
+        assert_eq!(segments("//etc/passwd").unwrap(), vec!["etc", "passwd"]);
+        for tail in &[
+            "..",
+            "a/../b",
+            "%2e%2e",
+            "%2E%2e/etc",
+            "..%2fetc",
+            "a%2f..%2f..",
+            "a%5c..%5cb",
+            "a%00.html",
+            "%ff",
+        ] {
+            assert_eq!(segments(tail), None, "{}", tail);
+        }
+    }
+
+    #[test]
+    fn resolve_stays_under_the_root() {
+        let dir = TempDir::new("resolve");
+        let file = dir.write("static/a/b.txt", "b");
+        let root = dir.0.join("static");
+        for &symlinks in &[Symlinks::Follow, Symlinks::Deny, Symlinks::Inside] {
+            assert_eq!(resolve(&root, "a/b.txt", symlinks), Some(file.clone()));
+            assert_eq!(resolve(&root, "a%2Fb.txt", symlinks), None);
+            assert_eq!(resolve(&root, "a/c.txt", symlinks), None);
+            assert_eq!(resolve(&root, "a/%2e%2e/a/b.txt", symlinks), None);
+            assert_eq!(resolve(&root, "//a/b.txt", symlinks), Some(file.clone()));
+            assert_eq!(resolve(&root, "//etc/passwd", symlinks), None);
+        }
+    }
+
+    #[test]
+    fn resolve_follows_symlinks_by_policy() {
+        let outside = TempDir::new("symlinks-outside");
+        let secret = outside.write("passwd", "secret");
+        let dir = TempDir::new("symlinks");
+        let file = dir.write("static/a/b.txt", "b");
+        let root = dir.0.join("static");
+        symlink(&file, root.join("inside.txt")).unwrap();
+        symlink(root.join("a"), root.join("inside-dir")).unwrap();
+        symlink(&secret, root.join("outside.txt")).unwrap();
+        symlink(&outside.0, root.join("outside-dir")).unwrap();
+
+        let resolve_all = |symlinks| {
+            [
+                "inside.txt",
+                "inside-dir/b.txt",
+                "outside.txt",
+                "outside-dir/passwd",
+            ]
+            .iter()
+            .map(|tail| resolve(&root, tail, symlinks))
+            .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            resolve_all(Symlinks::Follow),
+            vec![
+                Some(file.clone()),
+                Some(file.clone()),
+                Some(secret.clone()),
+                Some(secret.clone())
+            ]
+        );
+        assert_eq!(resolve_all(Symlinks::Deny), vec![None, None, None, None]);
+        assert_eq!(
+            resolve_all(Symlinks::Inside),
+            vec![Some(file.clone()), Some(file), None, None]
+        );
+    }
+}
@@ -0,0 +1,298 @@
+use crate::config::Config;
+use crate::errors::{GenericError, PathError};
+use std::ffi::CString;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::net::SocketAddr;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use tokio::net::{TcpListener, TcpSocket, UnixListener};
+
+/// An address to listen on, as written in the config.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Address {
+    /// `ip:port` or `hostname:port`, resolved when binding.
+    Tcp(String),
+    /// `unix:/path/to/socket`
+    Unix(PathBuf),
+}
+
+impl Address {
+    pub fn parse(text: &str) -> Address {
+        match text.strip_prefix("unix:") {
+            Some(path) => Address::Unix(PathBuf::from(path)),
+            None => Address::Tcp(text.to_owned()),
+        }
+    }
+
+    /// The addresses in `listen`, or `host` and `port` if `listen` is empty.
+    pub fn from_config(config: &Config) -> Vec<Address> {
+        if config.listen.is_empty() {
+            let host = if config.host.contains(':') {
+                format!("[{}]", config.host)
+            } else {
+                config.host.clone()
+            };
+            vec![Address::Tcp(format!("{}:{}", host, config.port))]
+        } else {
+            config
+                .listen
+                .iter()
+                .map(|text| Address::parse(text))
+                .collect()
+        }
+    }
+}
+
+/// A bound socket.
+pub enum Listener {
+    Tcp(TcpListener, SocketAddr),
+    Unix(UnixListener, PathBuf),
+}
+
+impl fmt::Display for Listener {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Listener::Tcp(_, address) => write!(f, "{}", address),
+            Listener::Unix(_, path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+/// Bind to `address`. A hostname is bound on every address it resolves to.
+pub async fn bind(address: &Address, config: &Config) -> Result<Vec<Listener>, GenericError> {
+    match address {
+        Address::Tcp(text) => {
+            let resolved = match tokio::net::lookup_host(text).await {
+                Ok(resolved) => resolved.collect::<Vec<_>>(),
+                Err(e) => Err(GenericError::BindError(format!(
+                    "unable to resolve {}: {}",
+                    text, e
+                )))?,
+            };
+            if resolved.is_empty() {
+                Err(GenericError::BindError(format!(
+                    "{} did not resolve to any address",
+                    text
+                )))?
+            }
+            let mut listeners = Vec::new();
+            for addr in resolved {
+                match bind_tcp(addr) {
+                    Ok(listener) => listeners.push(Listener::Tcp(listener, addr)),
+                    Err(e) => Err(GenericError::BindError(format!("{}: {}", addr, e)))?,
+                }
+            }
+            Ok(listeners)
+        }
+        Address::Unix(path) => Ok(vec![bind_unix(path, config)?]),
+    }
+}
+
+pub fn bind_tcp(addr: SocketAddr) -> io::Result<TcpListener> {
+    let socket = if addr.is_ipv4() {
+        TcpSocket::new_v4()?
+    } else {
+        let socket = TcpSocket::new_v6()?;
+        // Without this `[::]` would also take the IPv4 port, and listening on
+        // both `0.0.0.0` and `[::]` would fail
+        set_only_v6(&socket)?;
+        socket
+    };
+    socket.set_reuseaddr(true)?;
+    socket.bind(addr)?;
+    socket.listen(1024)
+}
+
+fn set_only_v6(socket: &TcpSocket) -> io::Result<()> {
+    let enable: libc::c_int = 1;
+    let result = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::IPPROTO_IPV6,
+            libc::IPV6_V6ONLY,
+            &enable as *const libc::c_int as *const libc::c_void,
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if result == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+fn bind_unix(path: &Path, config: &Config) -> Result<Listener, GenericError> {
+    let path_error = |text: String| PathError::new(path.to_owned(), text);
+
+    // A socket left behind by a previous run would make binding fail
+    if let Ok(metadata) = fs::symlink_metadata(path) {
+        if !metadata.file_type().is_socket() {
+            Err(path_error("exists and is not a socket".to_owned()))?
+        }
+        fs::remove_file(path).map_err(|e| path_error(e.to_string()))?;
+    }
+    let listener = UnixListener::bind(path)
+        .map_err(|e| GenericError::BindError(format!("{}: {}", path.display(), e)))?;
+
+    if let Some(mode) = config.unix_mode() {
+        fs::set_permissions(path, fs::Permissions::from_mode(mode))
+            .map_err(|e| path_error(e.to_string()))?;
+    }
+    if let Some(owner) = &config.unix_owner {
+        let (user, group) = match owner.find(':') {
+            Some(i) => (&owner[..i], Some(&owner[i + 1..])),
+            None => (owner.as_str(), None),
+        };
+        let uid = user_id(user)?;
+        // -1 leaves the group as it is
+        let gid = match group {
+            Some(group) => group_id(group)?,
+            None => libc::gid_t::MAX,
+        };
+        let c_path =
+            CString::new(path.as_os_str().as_bytes()).map_err(|e| path_error(e.to_string()))?;
+        if unsafe { libc::chown(c_path.as_ptr(), uid, gid) } != 0 {
+            Err(path_error(io::Error::last_os_error().to_string()))?
+        }
+    }
+
+    Ok(Listener::Unix(listener, path.to_owned()))
+}
+
+/// A user id, either as a number or looked up by name.
+fn user_id(user: &str) -> Result<u32, GenericError> {
+    if let Ok(uid) = user.parse() {
+        return Ok(uid);
+    }
+    let name = CString::new(user).map_err(|_| format!("Invalid user name {:?}", user))?;
+    let passwd = unsafe { libc::getpwnam(name.as_ptr()) };
+    if passwd.is_null() {
+        Err(GenericError::ConfigError(vec![format!(
+            "unix_owner names an unknown user {:?}",
+            user
+        )]))?
+    }
+    Ok(unsafe { (*passwd).pw_uid })
+}
+
+/// A group id, either as a number or looked up by name.
+fn group_id(group: &str) -> Result<u32, GenericError> {
+    if let Ok(gid) = group.parse() {
+        return Ok(gid);
+    }
+    let name = CString::new(group).map_err(|_| format!("Invalid group name {:?}", group))?;
+    let entry = unsafe { libc::getgrnam(name.as_ptr()) };
+    if entry.is_null() {
+        Err(GenericError::ConfigError(vec![format!(
+            "unix_owner names an unknown group {:?}",
+            group
+        )]))?
+    }
+    Ok(unsafe { (*entry).gr_gid })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ConfigBuilder;
+    use crate::files::tests::TempDir;
+
+    fn config(host: &str, port: u16, listen: &[&str]) -> Config {
+        let mut config = ConfigBuilder::default().build().unwrap();
+        config.host = host.to_owned();
+        config.port = port;
+        config.listen = listen.iter().map(|text| text.to_string()).collect();
+        config
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!(
+            Address::parse("unix:/run/sstatic.sock"),
+            Address::Unix(PathBuf::from("/run/sstatic.sock"))
+        );
+        assert_eq!(
+            Address::parse("unix:relative.sock"),
+            Address::Unix(PathBuf::from("relative.sock"))
+        );
+        assert_eq!(
+            Address::parse("127.0.0.1:8080"),
+            Address::Tcp("127.0.0.1:8080".to_owned())
+        );
+        assert_eq!(
+            Address::parse("[::1]:8080"),
+            Address::Tcp("[::1]:8080".to_owned())
+        );
+        // Only the prefix selects a unix socket
+        assert_eq!(
+            Address::parse("localhost:80"),
+            Address::Tcp("localhost:80".to_owned())
+        );
+    }
+
+    #[test]
+    fn host_and_port() {
+        assert_eq!(
+            Address::from_config(&config("0.0.0.0", 80, &[])),
+            vec![Address::Tcp("0.0.0.0:80".to_owned())]
+        );
+        // IPv6 hosts are bracketed so that the port can be told apart
+        assert_eq!(
+            Address::from_config(&config("::1", 8080, &[])),
+            vec![Address::Tcp("[::1]:8080".to_owned())]
+        );
+    }
+
+    #[test]
+    fn listen_replaces_host_and_port() {
+        assert_eq!(
+            Address::from_config(&config(
+                "::1",
+                8080,
+                &["127.0.0.1:80", "[::]:80", "unix:/run/sstatic.sock"]
+            )),
+            vec![
+                Address::Tcp("127.0.0.1:80".to_owned()),
+                Address::Tcp("[::]:80".to_owned()),
+                Address::Unix(PathBuf::from("/run/sstatic.sock")),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn addresses_need_a_port() {
+        let config = config("127.0.0.1", 80, &[]);
+        for text in &["127.0.0.1", "[::1]", "::1:"] {
+            match bind(&Address::parse(text), &config).await {
+                Err(GenericError::BindError(_)) => {}
+                Err(e) => panic!("{}: {}", text, e),
+                Ok(_) => panic!("{} was bound", text),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn bind_tcp_and_unix() {
+        let config = config("127.0.0.1", 80, &[]);
+        let listeners = bind(&Address::parse("127.0.0.1:0"), &config).await.unwrap();
+        assert!(matches!(listeners[..], [Listener::Tcp(_, addr)] if addr.ip().is_loopback()));
+
+        let dir = TempDir::new("listen");
+        let path = dir.0.join("sstatic.sock");
+        let address = Address::parse(&format!("unix:{}", path.display()));
+        let listeners = bind(&address, &config).await.unwrap();
+        assert_eq!(listeners[0].to_string(), format!("unix:{}", path.display()));
+        drop(listeners);
+
+        // A stale socket is replaced, anything else is left alone
+        bind(&address, &config).await.unwrap();
+        let file = dir.write("file", "");
+        let address = Address::parse(&format!("unix:{}", file.display()));
+        assert!(bind(&address, &config).await.is_err());
+        assert!(file.exists());
+    }
+}
@@ -0,0 +1,42 @@
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use std::io::Write;
+
+/// Writes application logs to stderr. Warnings and errors are highlighted
+/// when stderr is a terminal.
+struct Logger {
+    color: bool,
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let highlight = self.color && record.level() <= Level::Warn;
+        let stderr = std::io::stderr();
+        let mut stderr = stderr.lock();
+        let _ = if highlight {
+            writeln!(
+                stderr,
+                "\u{001b}[3;91m{:<5} {}\u{001b}[0m",
+                record.level(),
+                record.args()
+            )
+        } else {
+            writeln!(stderr, "{:<5} {}", record.level(), record.args())
+        };
+    }
+
+    fn flush(&self) {}
+}
+
+pub fn init(level: LevelFilter) {
+    let color = unsafe { libc::isatty(libc::STDERR_FILENO) } == 1;
+    if log::set_boxed_logger(Box::new(Logger { color })).is_ok() {
+        log::set_max_level(level);
+    }
+}
@@ -1,111 +1,409 @@
-use args::MainArgs;
-use config::ConfigBuilder;
-use files::Mimetypes;
+use access_log::AccessLog;
+use args::{Command, MainArgs};
+use cache::Lookup;
+use config::{Config, ConfigBuilder};
+use errors::GenericError;
+use listen::{Address, Listener};
+use log::{info, LevelFilter};
+use metrics::Metrics;
+use request::Request;
+use schedule::{Clock, SystemClock};
+use server::Routes;
+use shutdown::Shutdown;
+use site::{Site, SiteHandle};
+use std::collections::HashSet;
 use std::env;
-use std::net::IpAddr;
+use std::net::SocketAddr;
+use std::os::unix::io::RawFd;
 use std::path::PathBuf;
-use template::Template;
-use warp::http::header::{HeaderMap, HeaderValue};
+use std::sync::Arc;
+use std::time::Duration;
+use tls::Certificates;
+use warp::path::Tail;
+use warp::reply::Reply;
 use warp::Filter;
 
+mod access_log;
 mod args;
+mod cache;
+mod cache_control;
+mod commands;
+mod conditional;
 mod config;
+mod endpoints;
 mod errors;
+mod escape;
 mod files;
+mod listen;
+mod logging;
+mod metrics;
+mod page;
+mod parser;
+mod reload;
+mod request;
+mod schedule;
+mod server;
+mod shutdown;
+mod site;
+mod systemd;
 mod template;
+mod tls;
+mod variables;
 
-#[tokio::main]
-async fn main() {
-    let args: MainArgs = argh::from_env();
+fn load_config(args: &MainArgs) -> Result<(Config, Option<PathBuf>), GenericError> {
     let env_path = env::var("SSTATIC_CONFIG_PATH").ok().map(PathBuf::from);
-    let config_path = args.config_path.clone().or(env_path);
-
-    let config = match ConfigBuilder::default()
-        .or_from_env()
-        .or_from_file(config_path)
-    {
-        Ok(config) => match config.or_from_cmd(args).build() {
-            Ok(config) => config,
-            Err(_) => panic!("Failed to build config, something is horribly wrong!"),
-        },
-        Err(e) => panic!(e),
+    let config_path = match args.config_path.clone().or(env_path) {
+        Some(path) => Some(path),
+        None if args.no_config => None,
+        None => ConfigBuilder::discover(),
     };
 
-    if config.static_path.clone().contains('/') {
-        panic!("Unsupported feature: static_path should not contain \"/\"");
+    let config = ConfigBuilder::default()
+        .or_from_env()?
+        .or_from_file(config_path.clone())?
+        .or_from_cmd(args.clone())
+        .build()?;
+    Ok((config, config_path))
+}
+
+/// The maintenance page, and the static files under `static_path` if static
+/// content is configured.
+fn routes(config: &Config, handle: SiteHandle, metrics: &Arc<Metrics>) -> Routes {
+    let maintenance_handle = handle.clone();
+    let maintenance = metrics.count(
+        "page",
+        request::request()
+            .map(move |request: Request| {
+                Box::new(maintenance_handle.current().reply(&request)) as Box<dyn Reply>
+            })
+            .boxed(),
+    );
+
+    if config.static_content.is_some() {
+        let mut prefix = warp::any().boxed();
+        for segment in config.static_path.split('/').filter(|s| !s.is_empty()) {
+            prefix = prefix.and(warp::path(segment.to_owned())).boxed();
+        }
+        // Without a prefix the maintenance page is served when no file matches
+        let fallback = config.static_path.trim_matches('/').is_empty();
+        let static_serve = metrics.count(
+            "static",
+            prefix
+                .and(warp::path::tail())
+                .and(request::request())
+                .and_then(move |tail: Tail, request: Request| {
+                    let site = handle.current();
+                    // The bare prefix is left for the maintenance page
+                    let bare = tail.as_str().is_empty();
+                    let lookup = match &site.cache {
+                        Some(cache) if !bare => cache.get(tail.as_str(), &request),
+                        _ => Lookup::Unknown,
+                    };
+                    let reply = match (lookup, &site.content_path) {
+                        (Lookup::Hit(reply), _) => Some(reply),
+                        (Lookup::Missing, _) => None,
+                        (Lookup::Unknown, Some(content_path)) if !bare => files::serve_file(
+                            &site.mimetypes,
+                            content_path,
+                            tail.as_str(),
+                            site.symlinks,
+                            &site.cache_control,
+                            &request,
+                        ),
+                        (Lookup::Unknown, _) => None,
+                    };
+                    async move {
+                        match reply {
+                            Some(reply) => Ok(reply),
+                            None if fallback || bare => Err(warp::reject::not_found()),
+                            None => Ok(files::simple_404()),
+                        }
+                    }
+                })
+                .boxed(),
+        );
+        static_serve.or(maintenance).unify().boxed()
+    } else {
+        maintenance
     }
+}
 
-    let mimetypes = match Mimetypes::try_fetch(&config.mime_types) {
-        Ok(m) => m,
-        Err(_) => Mimetypes::default(),
+fn main() {
+    let args: MainArgs = argh::from_env();
+    // Changing the environment is only safe before the runtime starts its
+    // threads
+    let activated_fds = systemd::activated_fds();
+    let runtime = match tokio::runtime::Runtime::new() {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            eprintln!("Error: unable to start the runtime: {}", e);
+            std::process::exit(70);
+        }
     };
+    if let Err(e) = runtime.block_on(run(args, activated_fds)) {
+        eprintln!("{}", e);
+        std::process::exit(e.exit_code());
+    }
+}
 
-    let (template, mut js_hashes, mut css_hashes) = match files::get_files(&config) {
-        Ok((html, css, js)) => match Template::new(html, css, js) {
-            Ok(template) => template,
-            Err(e) => panic!("Error: {}", e),
+async fn run(args: MainArgs, activated_fds: Option<Vec<RawFd>>) -> Result<(), GenericError> {
+    let (config, config_path) = load_config(&args)?;
+    // The level was checked when the config was built
+    logging::init(config.log_level.parse().unwrap_or(LevelFilter::Info));
+    match &config_path {
+        Some(path) => info!("Loaded config from {}", path.display()),
+        None => info!("No config file, using the environment and command line only"),
+    }
+
+    let clock: Arc<dyn Clock> = Arc::new(SystemClock);
+    let site = Site::load(config.clone(), clock.clone())?;
+    match &args.command {
+        Some(Command::Check(_)) => return commands::check(&site).await,
+        Some(Command::Render(render)) => return commands::render(&site, render).await,
+        None => {}
+    }
+    let handle = SiteHandle::new(site);
+
+    let reload_interval = match config.reload_interval {
+        0 => None,
+        seconds => Some(Duration::from_secs(seconds)),
+    };
+    tokio::spawn(cache::watch(handle.clone()));
+    tokio::spawn(reload::watch(
+        handle.clone(),
+        config_path.into_iter().collect(),
+        reload_interval,
+        move || {
+            let (config, _) = load_config(&args)?;
+            Site::load(config, clock.clone())
         },
-        Err(e) => panic!("Error: {}", e),
+    ));
+
+    let certificates = match (&config.tls_cert, &config.tls_key) {
+        (Some(cert), Some(key)) => Some(Arc::new(Certificates::load(cert, key)?)),
+        _ => None,
+    };
+
+    let metrics = Arc::new(Metrics::default());
+    let endpoints = endpoints::routes(&config, handle.clone(), metrics.clone());
+    let routes = routes(&config, handle.clone(), &metrics);
+
+    let routes = match endpoints {
+        Some(endpoints) => endpoints.or(routes).unify().boxed(),
+        None => routes,
+    };
+    let routes = match AccessLog::open(&config)? {
+        Some(access_log) => Arc::new(access_log).wrap(routes),
+        None => routes,
+    };
+
+    // Sockets passed by systemd are used instead of the configured ones, and
+    // are left for systemd to clean up
+    let mut unix_paths = Vec::new();
+    let listeners = match activated_fds {
+        Some(fds) => match systemd::listeners(&fds) {
+            Ok(listeners) => listeners,
+            Err(e) => Err(GenericError::BindError(format!(
+                "unable to use the sockets passed by systemd: {}",
+                e
+            )))?,
+        },
+        None => {
+            let mut listeners = Vec::new();
+            for address in Address::from_config(&config) {
+                listeners.extend(listen::bind(&address, &config).await?);
+            }
+            for listener in &listeners {
+                if let Listener::Unix(_, path) = listener {
+                    unix_paths.push(path.clone());
+                }
+            }
+            listeners
+        }
     };
 
-    if js_hashes.is_empty() {
-        js_hashes.push("'none'".to_owned());
+    let shutdown = Shutdown::listen();
+    let acceptor = certificates
+        .as_ref()
+        .map(|certificates| certificates.acceptor());
+    if let Some(certificates) = certificates {
+        tokio::spawn(tls::watch(certificates));
     }
-    if css_hashes.is_empty() {
-        css_hashes.push("'none'".to_owned());
+
+    let mut servers = Vec::new();
+    let mut redirected = HashSet::new();
+    for listener in listeners {
+        match &acceptor {
+            Some(_) => info!("Serving maintenance page on {} over https", listener),
+            None => info!("Serving maintenance page on {}", listener),
+        }
+        let signal = shutdown.clone().wait();
+        match listener {
+            Listener::Tcp(listener, addr) => {
+                if let Some(redirect_port) = config.redirect_port {
+                    if redirected.insert(addr.ip()) {
+                        let redirect_addr = SocketAddr::new(addr.ip(), redirect_port);
+                        let redirect_listener = match listen::bind_tcp(redirect_addr) {
+                            Ok(listener) => listener,
+                            Err(e) => {
+                                Err(GenericError::BindError(format!("{}: {}", redirect_addr, e)))?
+                            }
+                        };
+                        info!("Redirecting http on {} to https", redirect_addr);
+                        servers.push(server::spawn(
+                            tls::redirect(addr.port()),
+                            server::accept_tcp(redirect_listener),
+                            None,
+                            shutdown.clone().wait(),
+                        ));
+                    }
+                }
+                let incoming = server::accept_tcp(listener);
+                servers.push(server::spawn(
+                    routes.clone(),
+                    incoming,
+                    acceptor.clone(),
+                    signal,
+                ));
+            }
+            Listener::Unix(listener, _) => {
+                let incoming = server::accept_unix(listener);
+                servers.push(server::spawn(
+                    routes.clone(),
+                    incoming,
+                    acceptor.clone(),
+                    signal,
+                ));
+            }
+        }
     }
 
-    let js_hashes = js_hashes.join(" ");
-    let css_hashes = css_hashes.join(" ");
+    systemd::notify("READY=1");
+    if let Some(interval) = systemd::watchdog_interval() {
+        tokio::spawn(systemd::watchdog(interval));
+    }
+    let stopping = shutdown.clone();
+    tokio::spawn(async move {
+        stopping.wait().await;
+        systemd::notify("STOPPING=1");
+    });
 
-    let csp = if config.unsafe_inline {
-        format!("default-src 'self'; script-src 'unsafe-inline'; style-src 'unsafe-inline';")
-    } else {
-        if template.unsafe_inline {
-            eprint!("\u{001b}[3;91m");
-            eprintln!("Some newlines in script or css tags were not minified correctly. Due to Content-Security-Policy, the site may not work correctly.");
-            eprintln!("Use --unsafe-inline -flag to use 'unsafe-inline' Content-Security-Policy to ignore this error message.");
-            eprint!("\u{001b}[0m");
+    let servers = async move {
+        for server in servers {
+            let _ = server.await;
         }
-        format!(
-            "default-src 'self'; script-src {}; style-src {};",
-            js_hashes, css_hashes
-        )
     };
+    shutdown
+        .drain(servers, Duration::from_secs(config.drain_timeout))
+        .await;
 
-    let mut headers = HeaderMap::new();
-    headers.insert("Content-Type", HeaderValue::from_static("text/html"));
-    headers.insert(
-        "Content-Security-Policy",
-        HeaderValue::from_str(&csp).unwrap(),
-    );
+    for path in unix_paths {
+        let _ = std::fs::remove_file(path);
+    }
+    Ok(())
+}
 
-    let host: IpAddr = if let Ok(ip) = config.host.clone().parse() {
-        ip
-    } else {
-        panic!("Unable to parse host address");
-    };
-    let port = config.port.into();
-    println!("Serving maintenance page on {}:{}", host, port);
-
-    let maintenance = warp::any()
-        .and(warp::header("Host"))
-        .and(warp::header("User-Agent"))
-        .map(move |host: String, ua: String| template.render(host, ua))
-        .map(|reply| warp::reply::with_status(reply, warp::http::StatusCode::SERVICE_UNAVAILABLE))
-        .with(warp::reply::with::headers(headers));
-
-    if let Some(static_content) = config.static_content.clone() {
-        let content_path = static_content.canonicalize().unwrap();
-        let static_serve = warp::path(config.static_path)
-            .and(warp::path::param::<String>())
-            .and(warp::path::end())
-            .map(move |path: String| files::serve_file(&mimetypes, &content_path, path));
-
-        warp::serve(static_serve.or(maintenance))
-            .run((host, port))
-            .await;
-    } else {
-        warp::serve(maintenance).run((host, port)).await;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use files::tests::TempDir;
+    use warp::http::StatusCode;
+
+    /// Routes for a site with `static/a.txt`, with and without the static
+    /// file cache.
+    fn sites(dir: &TempDir, static_path: &str) -> Vec<Routes> {
+        let html = dir.write("index.html", "<p>Down</p>");
+        let static_content = dir.write("static/a.txt", "a");
+        dir.write("static/sub/b.txt", "b");
+        vec!["0", "1M"]
+            .into_iter()
+            .map(|cache_size| {
+                let mut config = ConfigBuilder::default().build().unwrap();
+                config.html = html.clone();
+                config.static_content = static_content.parent().map(PathBuf::from);
+                config.static_path = static_path.to_owned();
+                config.static_cache_size = cache_size.to_owned();
+                let site = Site::load(config.clone(), Arc::new(SystemClock)).unwrap();
+                routes(
+                    &config,
+                    SiteHandle::new(site),
+                    &Arc::new(Metrics::default()),
+                )
+            })
+            .collect()
+    }
+
+    async fn get(routes: &Routes, path: &str) -> (StatusCode, String) {
+        let response = warp::test::request().path(path).reply(routes).await;
+        let body = String::from_utf8(response.body().to_vec()).unwrap();
+        (response.status(), body)
+    }
+
+    fn page() -> (StatusCode, String) {
+        (StatusCode::SERVICE_UNAVAILABLE, "<p>Down</p>".to_owned())
+    }
+
+    fn not_found() -> (StatusCode, String) {
+        (StatusCode::NOT_FOUND, "404".to_owned())
+    }
+
+    #[tokio::test]
+    async fn multi_segment_static_path() {
+        let dir = TempDir::new("routes-prefix");
+        for routes in &sites(&dir, "a/b") {
+            assert_eq!(
+                get(routes, "/a/b/a.txt").await,
+                (StatusCode::OK, "a".to_owned())
+            );
+            assert_eq!(
+                get(routes, "/a/b/sub/b.txt").await,
+                (StatusCode::OK, "b".to_owned())
+            );
+            // Missing files under the prefix are not the maintenance page
+            assert_eq!(get(routes, "/a/b/missing.txt").await, not_found());
+            // Only the whole prefix matches
+            assert_eq!(get(routes, "/a/a.txt").await, page());
+            assert_eq!(get(routes, "/a/bb/a.txt").await, page());
+            assert_eq!(get(routes, "/a.txt").await, page());
+            // The bare prefix serves the page
+            assert_eq!(get(routes, "/a/b").await, page());
+            assert_eq!(get(routes, "/a/b/").await, page());
+            assert_eq!(get(routes, "/").await, page());
+        }
+    }
+
+    #[tokio::test]
+    async fn empty_static_path_falls_back_to_the_page() {
+        let dir = TempDir::new("routes-empty");
+        for static_path in &["", "/"] {
+            for routes in &sites(&dir, static_path) {
+                assert_eq!(
+                    get(routes, "/a.txt").await,
+                    (StatusCode::OK, "a".to_owned())
+                );
+                assert_eq!(
+                    get(routes, "/sub/b.txt").await,
+                    (StatusCode::OK, "b".to_owned())
+                );
+                assert_eq!(get(routes, "/missing.txt").await, page());
+                assert_eq!(get(routes, "/sub/").await, page());
+                assert_eq!(get(routes, "/").await, page());
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn without_static_content() {
+        let dir = TempDir::new("routes-none");
+        let mut config = ConfigBuilder::default().build().unwrap();
+        config.html = dir.write("index.html", "<p>Down</p>");
+        let site = Site::load(config.clone(), Arc::new(SystemClock)).unwrap();
+        let routes = routes(
+            &config,
+            SiteHandle::new(site),
+            &Arc::new(Metrics::default()),
+        );
+        assert_eq!(get(&routes, "/static/a.txt").await, page());
+        assert_eq!(get(&routes, "/").await, page());
     }
 }
@@ -0,0 +1,191 @@
+use crate::server::Routes;
+use crate::site::Site;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use warp::hyper::body::HttpBody;
+use warp::reply::{Reply, Response};
+use warp::Filter;
+
+/// Upper bounds of the latency histogram buckets, in seconds.
+const BUCKETS: &[f64] = &[
+    0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5,
+];
+
+#[derive(Default)]
+struct Histogram {
+    /// Count of observations in each of `BUCKETS`, not cumulative.
+    buckets: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, seconds: f64) {
+        if self.buckets.is_empty() {
+            self.buckets = vec![0; BUCKETS.len()];
+        }
+        if let Some(i) = BUCKETS.iter().position(|bound| seconds <= *bound) {
+            self.buckets[i] += 1;
+        }
+        self.sum += seconds;
+        self.count += 1;
+    }
+}
+
+#[derive(Default)]
+struct Counters {
+    requests: BTreeMap<(&'static str, u16), u64>,
+    bytes: BTreeMap<&'static str, u64>,
+    latency: BTreeMap<&'static str, Histogram>,
+}
+
+/// Request counters, exposed in the Prometheus text format.
+#[derive(Default)]
+pub struct Metrics {
+    counters: Mutex<Counters>,
+    /// Size of the last served maintenance page.
+    page_size: AtomicU64,
+}
+
+impl Metrics {
+    /// Wrap `routes` so that every reply is counted under `route`.
+    pub fn count(self: &Arc<Self>, route: &'static str, routes: Routes) -> Routes {
+        let metrics = self.clone();
+        warp::any()
+            .map(Instant::now)
+            .and(routes)
+            .map(move |start: Instant, reply: Box<dyn Reply>| {
+                let response = reply.into_response();
+                metrics.record(route, &response, start.elapsed());
+                Box::new(response) as Box<dyn Reply>
+            })
+            .boxed()
+    }
+
+    fn record(&self, route: &'static str, response: &Response, elapsed: Duration) {
+        let size = response.body().size_hint().exact().unwrap_or(0);
+        if route == "page" {
+            self.page_size.store(size, Ordering::Relaxed);
+        }
+
+        let mut counters = self.counters.lock().unwrap();
+        *counters
+            .requests
+            .entry((route, response.status().as_u16()))
+            .or_default() += 1;
+        *counters.bytes.entry(route).or_default() += size;
+        counters
+            .latency
+            .entry(route)
+            .or_default()
+            .observe(elapsed.as_secs_f64());
+    }
+
+    pub fn render(&self, site: &Site) -> String {
+        let counters = self.counters.lock().unwrap();
+        let mut out = String::new();
+
+        header(
+            &mut out,
+            "sstatic_requests_total",
+            "counter",
+            "Requests served, by route and status code.",
+        );
+        for ((route, status), count) in &counters.requests {
+            writeln!(
+                out,
+                "sstatic_requests_total{{route=\"{}\",status=\"{}\"}} {}",
+                route, status, count
+            )
+            .unwrap();
+        }
+
+        header(
+            &mut out,
+            "sstatic_response_bytes_total",
+            "counter",
+            "Bytes of response bodies served, by route.",
+        );
+        for (route, bytes) in &counters.bytes {
+            writeln!(
+                out,
+                "sstatic_response_bytes_total{{route=\"{}\"}} {}",
+                route, bytes
+            )
+            .unwrap();
+        }
+
+        header(
+            &mut out,
+            "sstatic_request_duration_seconds",
+            "histogram",
+            "Time taken to build the response, by route.",
+        );
+        for (route, histogram) in &counters.latency {
+            let mut cumulative = 0;
+            for (bound, count) in BUCKETS.iter().zip(&histogram.buckets) {
+                cumulative += count;
+                writeln!(
+                    out,
+                    "sstatic_request_duration_seconds_bucket{{route=\"{}\",le=\"{}\"}} {}",
+                    route, bound, cumulative
+                )
+                .unwrap();
+            }
+            writeln!(
+                out,
+                "sstatic_request_duration_seconds_bucket{{route=\"{}\",le=\"+Inf\"}} {}",
+                route, histogram.count
+            )
+            .unwrap();
+            writeln!(
+                out,
+                "sstatic_request_duration_seconds_sum{{route=\"{}\"}} {}",
+                route, histogram.sum
+            )
+            .unwrap();
+            writeln!(
+                out,
+                "sstatic_request_duration_seconds_count{{route=\"{}\"}} {}",
+                route, histogram.count
+            )
+            .unwrap();
+        }
+
+        header(
+            &mut out,
+            "sstatic_template_build_seconds",
+            "gauge",
+            "Time taken to build the templates when the site was last loaded.",
+        );
+        writeln!(
+            out,
+            "sstatic_template_build_seconds {}",
+            site.build_time.as_secs_f64()
+        )
+        .unwrap();
+
+        header(
+            &mut out,
+            "sstatic_page_size_bytes",
+            "gauge",
+            "Size of the last served maintenance page.",
+        );
+        writeln!(
+            out,
+            "sstatic_page_size_bytes {}",
+            self.page_size.load(Ordering::Relaxed)
+        )
+        .unwrap();
+
+        out
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    writeln!(out, "# HELP {} {}", name, help).unwrap();
+    writeln!(out, "# TYPE {} {}", name, kind).unwrap();
+}
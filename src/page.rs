@@ -0,0 +1,172 @@
+use crate::cache_control::CachePolicy;
+use crate::conditional::Validators;
+use crate::config::Config;
+use crate::errors::GenericError;
+use crate::files;
+use crate::reload;
+use crate::request::{self, Request};
+use crate::template::Template;
+use crate::variables::Variables;
+use log::warn;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use warp::http::header::{HeaderMap, HeaderValue, CACHE_CONTROL};
+use warp::http::StatusCode;
+use warp::hyper::Body;
+use warp::reply::Response;
+
+/// A served html page, along with the headers it is always served with.
+pub struct Page {
+    pub template: Template,
+    headers: HeaderMap,
+    /// Every file the page was built from.
+    pub paths: Vec<PathBuf>,
+    /// Whether the page renders the same for every request, so it can be
+    /// answered with `304 Not Modified`.
+    cacheable: bool,
+    /// The newest modification time of the files the page was built from,
+    /// used as its `Last-Modified`.
+    modified: Option<SystemTime>,
+    /// Whether the Content-Security-Policy is computed from each rendered
+    /// page, as variables change the contents of its script or style blocks.
+    dynamic_csp: bool,
+}
+
+impl Page {
+    pub fn load(
+        config: &Config,
+        html: &Path,
+        cache_control: &CachePolicy,
+    ) -> Result<Page, GenericError> {
+        let files = files::get_files(config, html)?;
+        let (template, js_hashes, css_hashes) = match Template::new(files.html, files.css, files.js)
+        {
+            Err(GenericError::SyntaxError(e)) => Err(files.sources.locate(e))?,
+            result => result?,
+        };
+
+        let csp = if config.unsafe_inline {
+            String::from(
+                "default-src 'self'; script-src 'unsafe-inline'; style-src 'unsafe-inline';",
+            )
+        } else {
+            if template.unsafe_inline {
+                warn!("Some newlines in script or css tags were not minified correctly. Due to Content-Security-Policy, the site may not work correctly.");
+                warn!("Use --unsafe-inline -flag to use 'unsafe-inline' Content-Security-Policy to ignore this error message.");
+            }
+            csp(&js_hashes, &css_hashes)
+        };
+        let dynamic_csp = template.dynamic_inline && !config.unsafe_inline;
+
+        let mut headers = HeaderMap::new();
+        headers.insert("Content-Type", HeaderValue::from_static("text/html"));
+        headers.insert(
+            "Content-Security-Policy",
+            HeaderValue::from_str(&csp).unwrap(),
+        );
+        if let Some(value) = cache_control.page() {
+            headers.insert(CACHE_CONTROL, value.clone());
+        }
+
+        // Variables from the environment are read once, when loading
+        let cacheable = template
+            .placeholders()
+            .iter()
+            .all(|name| !request::is_provided(name) || name.starts_with("env."));
+
+        let modified = newest_modified(&files.paths);
+        Ok(Page {
+            template,
+            headers,
+            paths: files.paths,
+            cacheable,
+            modified,
+            dynamic_csp,
+        })
+    }
+
+    /// The validators of the page rendered with `variables`, or None if the
+    /// page renders differently for each request.
+    pub fn validators(&self, variables: &Variables) -> Option<Validators> {
+        if !self.cacheable {
+            return None;
+        }
+        let body = self.template.render(variables);
+        Some(Validators::new(body.as_bytes(), self.modified))
+    }
+
+    /// Render the page for `request`. `validators` are the ones computed for
+    /// the same `variables` when the site was loaded.
+    pub fn reply(
+        &self,
+        request: &Request,
+        variables: &Variables,
+        status: StatusCode,
+        retry_after: Option<&HeaderValue>,
+        validators: Option<&Validators>,
+    ) -> Response {
+        // Conditional requests only apply to successful responses
+        let validators = validators.filter(|_| status.is_success());
+        if let Some(validators) = validators {
+            if validators.fresh(request) {
+                let mut response = validators.not_modified();
+                if let Some(value) = self.headers.get(CACHE_CONTROL) {
+                    response.headers_mut().insert(CACHE_CONTROL, value.clone());
+                }
+                return response;
+            }
+        }
+
+        let body = self.template.render(variables);
+
+        let dynamic_csp = if self.dynamic_csp {
+            let (js_hashes, css_hashes) = Template::hashes(&body);
+            HeaderValue::from_str(&csp(&js_hashes, &css_hashes)).ok()
+        } else {
+            None
+        };
+
+        let mut response = Response::new(Body::from(body));
+        *response.status_mut() = status;
+        let headers = response.headers_mut();
+        for (key, value) in &self.headers {
+            headers.insert(key, value.clone());
+        }
+        if let Some(csp) = dynamic_csp {
+            headers.insert("Content-Security-Policy", csp);
+        }
+        if let Some(retry_after) = retry_after {
+            headers.insert("Retry-After", retry_after.clone());
+        }
+        if let Some(validators) = validators {
+            validators.apply(headers);
+        }
+        response
+    }
+}
+
+/// The newest modification time of `paths` and the files in them, or None
+/// if none of them could be read, eg. when the built-in page is used.
+fn newest_modified(paths: &[PathBuf]) -> Option<SystemTime> {
+    reload::fingerprint(paths)
+        .into_iter()
+        .filter_map(|(_, modified, _)| modified)
+        .max()
+}
+
+/// The Content-Security-Policy that allows only the given script and style
+/// hashes.
+fn csp(js_hashes: &[String], css_hashes: &[String]) -> String {
+    let sources = |hashes: &[String]| {
+        if hashes.is_empty() {
+            "'none'".to_owned()
+        } else {
+            hashes.join(" ")
+        }
+    };
+    format!(
+        "default-src 'self'; script-src {}; style-src {};",
+        sources(js_hashes),
+        sources(css_hashes)
+    )
+}
@@ -0,0 +1,877 @@
+use crate::errors::SyntaxError;
+use crate::escape::Escape;
+use crate::template::Tag;
+use crate::variables::{Scope, Value};
+
+/// Bytes reserved in the render buffer for each placeholder.
+const EXPR_SIZE_HINT: usize = 64;
+
+/// A piece of the compiled template.
+#[derive(Debug, Clone)]
+pub enum Node {
+    Text(String),
+    Expr(Expr),
+    If {
+        condition: Condition,
+        then: Vec<Node>,
+        otherwise: Vec<Node>,
+    },
+    For {
+        item: String,
+        list: String,
+        body: Vec<Node>,
+    },
+}
+
+impl Node {
+    pub fn render<'a>(&'a self, scope: &mut Scope<'a>, out: &mut String) {
+        match self {
+            Node::Text(text) => out.push_str(text),
+            Node::Expr(expr) => expr.write(scope, out),
+            Node::If {
+                condition,
+                then,
+                otherwise,
+            } => {
+                let nodes = if condition.eval(scope) {
+                    then
+                } else {
+                    otherwise
+                };
+                for node in nodes {
+                    node.render(scope, out);
+                }
+            }
+            Node::For { item, list, body } => {
+                if let Some(Value::List(list)) = scope.get(list) {
+                    for value in list {
+                        scope.push(item, value);
+                        for node in body {
+                            node.render(scope, out);
+                        }
+                        scope.pop();
+                    }
+                }
+            }
+        }
+    }
+
+    /// Replace every `{{ name }}` placeholder with `text`, which is not
+    /// parsed or escaped.
+    pub fn substitute(&mut self, name: &str, text: &str) {
+        match self {
+            Node::Expr(expr) if expr.name == name => *self = Node::Text(text.to_owned()),
+            Node::Text(_) | Node::Expr(_) => {}
+            Node::If {
+                then, otherwise, ..
+            } => {
+                for node in then.iter_mut().chain(otherwise) {
+                    node.substitute(name, text);
+                }
+            }
+            Node::For { body, .. } => {
+                for node in body {
+                    node.substitute(name, text);
+                }
+            }
+        }
+    }
+
+    /// Rough size of the rendered node, used to pre-size the render buffer.
+    pub fn size_hint(&self) -> usize {
+        match self {
+            Node::Text(text) => text.len(),
+            Node::Expr(_) => EXPR_SIZE_HINT,
+            Node::If {
+                then, otherwise, ..
+            } => then
+                .iter()
+                .chain(otherwise)
+                .map(Node::size_hint)
+                .max()
+                .unwrap_or(0),
+            Node::For { body, .. } => body.iter().map(Node::size_hint).sum(),
+        }
+    }
+
+    /// Collect the names of all variables the node uses, leaving out loop
+    /// variables.
+    pub fn names<'a>(&'a self, locals: &mut Vec<&'a str>, names: &mut Vec<&'a str>) {
+        let mut add = |name: &'a str, locals: &[&str]| {
+            let is_local = locals.iter().any(|local| {
+                name == *local
+                    || matches!(name.strip_prefix(local), Some(rest) if rest.starts_with('.'))
+            });
+            if !is_local {
+                names.push(name);
+            }
+        };
+        match self {
+            Node::Text(_) => {}
+            Node::Expr(expr) => add(&expr.name, locals),
+            Node::If {
+                condition,
+                then,
+                otherwise,
+            } => {
+                add(&condition.name, locals);
+                for node in then.iter().chain(otherwise) {
+                    node.names(locals, names);
+                }
+            }
+            Node::For { item, list, body } => {
+                add(list, locals);
+                locals.push(item);
+                for node in body {
+                    node.names(locals, names);
+                }
+                locals.pop();
+            }
+        }
+    }
+}
+
+/// A `{{ ... }}` placeholder, filled on every render.
+#[derive(Debug, Clone)]
+pub struct Expr {
+    name: String,
+    escape: Escape,
+    filters: Vec<Filter>,
+}
+
+impl Expr {
+    fn write<'a>(&'a self, scope: &Scope<'a>, out: &mut String) {
+        let mut value = scope.get(&self.name);
+        for filter in &self.filters {
+            value = filter.apply(value);
+        }
+        if let Some(value) = value {
+            value.write(self.escape, out);
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Filter {
+    Default(Value),
+}
+
+impl Filter {
+    fn apply<'a>(&'a self, value: Option<&'a Value>) -> Option<&'a Value> {
+        match self {
+            Filter::Default(default) => match value {
+                Some(value) if value.is_truthy() => Some(value),
+                _ => Some(default),
+            },
+        }
+    }
+}
+
+/// The condition of an `{% if %}` block.
+#[derive(Debug, Clone)]
+pub struct Condition {
+    name: String,
+    negated: bool,
+}
+
+impl Condition {
+    fn eval(&self, scope: &Scope) -> bool {
+        matches!(scope.get(&self.name), Some(value) if value.is_truthy()) != self.negated
+    }
+}
+
+/// Compiles template text into nodes. The escaping of each placeholder is
+/// decided here, so that nothing has to be figured out when rendering.
+pub struct Parser<'a> {
+    text: &'a str,
+    pos: usize,
+    scanner: ContextScanner<'a>,
+    /// Whether any tag sits inside a `<script>` or `<style>` block.
+    inline: bool,
+}
+
+impl<'a> Parser<'a> {
+    /// Compile `text`, and tell whether any tag sits inside a `<script>` or
+    /// `<style>` block, so that the rendered block differs from the text.
+    pub fn parse(text: &'a str) -> Result<(Vec<Node>, bool), SyntaxError> {
+        let mut parser = Parser {
+            text,
+            pos: 0,
+            scanner: ContextScanner::new(text),
+            inline: false,
+        };
+        let (nodes, _) = parser.parse_block(None, &[])?;
+        Ok((nodes, parser.inline))
+    }
+
+    /// Parse nodes until one of the `ends` tags, which is returned along with
+    /// the nodes. `opened` is the offset and keyword of the tag that started
+    /// the block, if any.
+    fn parse_block(
+        &mut self,
+        opened: Option<(usize, &str)>,
+        ends: &[&str],
+    ) -> Result<(Vec<Node>, String), SyntaxError> {
+        let mut nodes = Vec::new();
+        loop {
+            let rest = &self.text[self.pos..];
+            let next = match (rest.find("{{"), rest.find("{%")) {
+                (Some(a), Some(b)) => Some(a.min(b)),
+                (a, b) => a.or(b),
+            };
+            let start =
+                match next {
+                    Some(i) => self.pos + i,
+                    None => {
+                        if !rest.is_empty() {
+                            nodes.push(Node::Text(rest.to_owned()));
+                        }
+                        self.pos = self.text.len();
+                        return match opened {
+                            Some((offset, keyword)) => Err(self
+                                .error(offset, format!("`{{% {} %}}` is never closed", keyword))),
+                            None => Ok((nodes, String::new())),
+                        };
+                    }
+                };
+            if start > self.pos {
+                nodes.push(Node::Text(self.text[self.pos..start].to_owned()));
+            }
+
+            let is_expr = self.text[start..].starts_with("{{");
+            let end = self.find_closing(start, if is_expr { "}}" } else { "%}" })?;
+            let inner = &self.text[start + 2..end];
+            self.pos = end + 2;
+
+            let escape = self.scanner.escape_at(start);
+            if matches!(escape, Escape::Script | Escape::Style) {
+                self.inline = true;
+            }
+            if is_expr {
+                nodes.push(Node::Expr(self.parse_expr(inner, start, escape)?));
+                continue;
+            }
+
+            let mut words = inner.split_whitespace();
+            let keyword = words.next().unwrap_or("").to_lowercase();
+            let args: Vec<&str> = words.collect();
+
+            if ends.contains(&&*keyword) {
+                if !args.is_empty() {
+                    return Err(self.error(start, format!("`{}` takes no arguments", keyword)));
+                }
+                return Ok((nodes, keyword));
+            }
+
+            match &*keyword {
+                "if" => {
+                    let condition = self.parse_condition(&args, start)?;
+                    let (then, end) = self.parse_block(Some((start, "if")), &["else", "endif"])?;
+                    let otherwise = if end == "else" {
+                        self.parse_block(Some((start, "if")), &["endif"])?.0
+                    } else {
+                        Vec::new()
+                    };
+                    nodes.push(Node::If {
+                        condition,
+                        then,
+                        otherwise,
+                    });
+                }
+                "for" => {
+                    let (item, list) = match &*args {
+                        [item, keyword, list] if keyword.eq_ignore_ascii_case("in") => {
+                            (item.to_lowercase(), list.to_lowercase())
+                        }
+                        _ => return Err(self.error(start, "expected `{% for item in list %}`")),
+                    };
+                    if !is_valid_name(&item) || item.contains('.') || !is_valid_name(&list) {
+                        return Err(self.error(start, "invalid variable name in `{% for %}`"));
+                    }
+                    let (body, _) = self.parse_block(Some((start, "for")), &["endfor"])?;
+                    nodes.push(Node::For { item, list, body });
+                }
+                "" => return Err(self.error(start, "empty `{% %}` tag")),
+                other => return Err(self.error(start, format!("unexpected `{{% {} %}}`", other))),
+            }
+        }
+    }
+
+    /// Find the closing delimiter of the tag starting at `start`, skipping
+    /// over any string literals.
+    fn find_closing(&self, start: usize, closing: &str) -> Result<usize, SyntaxError> {
+        let bytes = self.text.as_bytes();
+        let mut in_string = false;
+        let mut i = start + 2;
+        while i < bytes.len() {
+            match bytes[i] {
+                b'\\' if in_string => i += 1,
+                b'"' => in_string = !in_string,
+                _ if !in_string && self.text[i..].starts_with(closing) => return Ok(i),
+                _ => {}
+            }
+            i += 1;
+        }
+        Err(self.error(
+            start,
+            format!("`{}` is never closed", &self.text[start..start + 2]),
+        ))
+    }
+
+    fn parse_expr(&self, inner: &str, offset: usize, escape: Escape) -> Result<Expr, SyntaxError> {
+        let mut segments = split_outside_strings(inner, '|').into_iter();
+        let mut words: Vec<&str> = segments.next().unwrap_or("").split_whitespace().collect();
+
+        let escape = if words.len() > 1 && words[0].eq_ignore_ascii_case("raw") {
+            words.remove(0);
+            Escape::Raw
+        } else {
+            escape
+        };
+        let name = match &*words {
+            [name] if is_valid_name(name) => name.to_lowercase(),
+            _ => return Err(self.error(offset, "expected a variable name")),
+        };
+
+        let mut filters = Vec::new();
+        for segment in segments {
+            let segment = segment.trim();
+            let (filter, args) = match segment.find('(') {
+                Some(i) if segment.ends_with(')') => {
+                    (&segment[..i], &segment[i + 1..segment.len() - 1])
+                }
+                _ => (segment, ""),
+            };
+            match &*filter.trim().to_lowercase() {
+                "default" => match parse_string(args) {
+                    Some(default) => filters.push(Filter::Default(Value::Text(default))),
+                    None => {
+                        return Err(self.error(
+                            offset,
+                            "`default` expects a string, eg. `default(\"none\")`",
+                        ))
+                    }
+                },
+                other => return Err(self.error(offset, format!("unknown filter `{}`", other))),
+            }
+        }
+
+        Ok(Expr {
+            name,
+            escape,
+            filters,
+        })
+    }
+
+    fn parse_condition(&self, args: &[&str], offset: usize) -> Result<Condition, SyntaxError> {
+        let (name, negated) = match args {
+            [name] => (name, false),
+            [not, name] if not.eq_ignore_ascii_case("not") => (name, true),
+            _ => {
+                return Err(self.error(
+                    offset,
+                    "expected `{% if variable %}` or `{% if not variable %}`",
+                ))
+            }
+        };
+        if !is_valid_name(name) {
+            return Err(self.error(offset, "invalid variable name in `{% if %}`"));
+        }
+        Ok(Condition {
+            name: name.to_lowercase(),
+            negated,
+        })
+    }
+
+    fn error<T: Into<String>>(&self, offset: usize, text: T) -> SyntaxError {
+        SyntaxError::at(self.text, offset, text)
+    }
+}
+
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('.')
+        && !name.ends_with('.')
+        && name
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '-' || c == '_' || c == '.')
+}
+
+fn split_outside_strings(text: &str, separator: char) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut in_string = false;
+    let mut escaped = false;
+    let mut last = 0;
+    for (i, c) in text.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            c if c == separator && !in_string => {
+                parts.push(&text[last..i]);
+                last = i + c.len_utf8();
+            }
+            _ => {}
+        }
+    }
+    parts.push(&text[last..]);
+    parts
+}
+
+/// Parse a double-quoted string literal.
+fn parse_string(text: &str) -> Option<String> {
+    let text = text.trim();
+    let inner = text.strip_prefix('"')?.strip_suffix('"')?;
+    let mut string = String::with_capacity(inner.len());
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => string.push(chars.next()?),
+            '"' => return None,
+            c => string.push(c),
+        }
+    }
+    Some(string)
+}
+
+enum State {
+    Text,
+    Comment,
+    Tag {
+        quote: Option<u8>,
+        raw: Option<Tag>,
+        /// The lowercase name of the current or last attribute.
+        name: Vec<u8>,
+        /// Whether `name` is still being read.
+        in_name: bool,
+        /// Where the value of the current attribute starts, after any quote.
+        value: Option<usize>,
+    },
+    Raw(Tag),
+}
+
+impl State {
+    fn tag(raw: Option<Tag>) -> State {
+        State::Tag {
+            quote: None,
+            raw,
+            name: Vec::new(),
+            in_name: false,
+            value: None,
+        }
+    }
+}
+
+/// Attributes whose value is a URL, where the scheme needs to be checked.
+const URL_ATTRIBUTES: &[&[u8]] = &[
+    b"action",
+    b"background",
+    b"cite",
+    b"data",
+    b"formaction",
+    b"href",
+    b"poster",
+    b"src",
+    b"xlink:href",
+];
+
+/// Walks through html text in one direction, keeping track of whether the
+/// current position is in plain text, inside a tag or inside a
+/// `<script>`/`<style>` block.
+struct ContextScanner<'a> {
+    text: &'a [u8],
+    pos: usize,
+    state: State,
+}
+
+impl<'a> ContextScanner<'a> {
+    fn new(text: &'a str) -> Self {
+        ContextScanner {
+            text: text.as_bytes(),
+            pos: 0,
+            state: State::Text,
+        }
+    }
+
+    /// Advance the scanner to `offset` and return the escaping required
+    /// there. Offsets must be given in increasing order.
+    fn escape_at(&mut self, offset: usize) -> Escape {
+        while self.pos < offset {
+            self.step();
+        }
+        match &self.state {
+            State::Text | State::Comment => Escape::Html,
+            // Until the scheme ends, a value could still turn the URL into
+            // `javascript:`
+            State::Tag {
+                name,
+                value: Some(start),
+                ..
+            } if URL_ATTRIBUTES.contains(&name.as_slice())
+                && !self.text[*start..offset]
+                    .iter()
+                    .any(|b| matches!(b, b':' | b'/' | b'?' | b'#')) =>
+            {
+                Escape::Url
+            }
+            State::Tag { .. } => Escape::Attribute,
+            State::Raw(Tag::Script) => Escape::Script,
+            State::Raw(Tag::Style) => Escape::Style,
+        }
+    }
+
+    fn step(&mut self) {
+        let rest = &self.text[self.pos..];
+        let byte = rest[0];
+        match &mut self.state {
+            State::Text => {
+                if rest.starts_with(b"<!--") {
+                    self.state = State::Comment;
+                    self.pos += 4;
+                    return;
+                }
+                if byte == b'<' && rest.len() > 1 && rest[1].is_ascii_alphabetic() {
+                    let name: Vec<u8> = rest[1..]
+                        .iter()
+                        .take_while(|b| b.is_ascii_alphanumeric())
+                        .map(|b| b.to_ascii_lowercase())
+                        .collect();
+                    let raw = match &*name {
+                        b"script" => Some(Tag::Script),
+                        b"style" => Some(Tag::Style),
+                        _ => None,
+                    };
+                    self.state = State::tag(raw);
+                } else if byte == b'<' && rest.get(1) == Some(&b'/') {
+                    self.state = State::tag(None);
+                }
+            }
+            State::Comment => {
+                if rest.starts_with(b"-->") {
+                    self.state = State::Text;
+                    self.pos += 3;
+                    return;
+                }
+            }
+            State::Tag {
+                quote,
+                raw,
+                name,
+                in_name,
+                value,
+            } => match (*quote, byte) {
+                (Some(q), b) if q == b => {
+                    *quote = None;
+                    *value = None;
+                }
+                (Some(_), _) => {}
+                // A value starting with a quote lasts until the same quote
+                (None, b'"') | (None, b'\'') if *value == Some(self.pos) => {
+                    *quote = Some(byte);
+                    *value = Some(self.pos + 1);
+                }
+                (None, b'"') | (None, b'\'') => *quote = Some(byte),
+                (None, b'>') => {
+                    self.state = match raw.take() {
+                        Some(tag) => State::Raw(tag),
+                        None => State::Text,
+                    }
+                }
+                (None, b'=') if value.is_none() => {
+                    *in_name = false;
+                    *value = Some(self.pos + 1);
+                }
+                // Spaces may come between `=` and the value, otherwise they
+                // end an unquoted value
+                (None, b) if b.is_ascii_whitespace() && *value == Some(self.pos) => {
+                    *value = Some(self.pos + 1);
+                }
+                (None, b) if b.is_ascii_whitespace() || b == b'/' && value.is_none() => {
+                    *in_name = false;
+                    *value = None;
+                }
+                (None, _) if value.is_some() => {}
+                (None, b) => {
+                    if !*in_name {
+                        name.clear();
+                        *in_name = true;
+                    }
+                    name.push(b.to_ascii_lowercase());
+                }
+            },
+            State::Raw(tag) => {
+                let closing = format!("</{}", tag.as_str());
+                if rest.len() >= closing.len()
+                    && rest[..closing.len()].eq_ignore_ascii_case(closing.as_bytes())
+                {
+                    self.state = State::tag(None);
+                }
+            }
+        }
+        self.pos += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::variables::Variables;
+    use std::collections::HashMap;
+
+    fn render(text: &str, variables: &Variables) -> String {
+        let (nodes, _) = Parser::parse(text).unwrap();
+        let mut out = String::new();
+        let mut scope = Scope::new(variables);
+        for node in &nodes {
+            node.render(&mut scope, &mut out);
+        }
+        out
+    }
+
+    fn variables() -> Variables {
+        let service = |name: &str, up: bool| {
+            let mut map = HashMap::new();
+            map.insert("name".to_owned(), Value::from(name));
+            map.insert("up".to_owned(), Value::Boolean(up));
+            Value::Map(map)
+        };
+        let mut map = HashMap::new();
+        map.insert("name".to_owned(), Value::from("<b>"));
+        map.insert("empty".to_owned(), Value::from(""));
+        map.insert(
+            "services".to_owned(),
+            Value::List(vec![service("Mail", true), service("Chat", false)]),
+        );
+        Variables::from(map)
+    }
+
+    fn error(text: &str) -> (usize, usize) {
+        let error = Parser::parse(text).unwrap_err();
+        (error.line, error.column)
+    }
+
+    #[test]
+    fn placeholders_and_defaults() {
+        let variables = variables();
+        assert_eq!(render("Hi {{ name }}!", &variables), "Hi &lt;b&gt;!");
+        assert_eq!(render("Hi {{NAME}}!", &variables), "Hi &lt;b&gt;!");
+        assert_eq!(render("{{ missing }}", &variables), "");
+        assert_eq!(
+            render(r#"{{ missing | default("none") }}"#, &variables),
+            "none"
+        );
+        assert_eq!(render(r#"{{ empty | default("a|b") }}"#, &variables), "a|b");
+        assert_eq!(
+            render(r#"{{ name | default("none") }}"#, &variables),
+            "&lt;b&gt;"
+        );
+    }
+
+    #[test]
+    fn if_else() {
+        let variables = variables();
+        let text = "{% if name %}yes{% else %}no{% endif %}";
+        assert_eq!(render(text, &variables), "yes");
+        let text = "{% if empty %}yes{% else %}no{% endif %}";
+        assert_eq!(render(text, &variables), "no");
+        let text = "{% if not missing %}yes{% endif %}";
+        assert_eq!(render(text, &variables), "yes");
+        let text = "{% IF missing %}yes{% ENDIF %}";
+        assert_eq!(render(text, &variables), "");
+    }
+
+    #[test]
+    fn for_loops() {
+        let variables = variables();
+        let text = "{% for service in services %}{{ service.name }};{% endfor %}";
+        assert_eq!(render(text, &variables), "Mail;Chat;");
+        let text = "{% for x in missing %}{{ x }}{% endfor %}";
+        assert_eq!(render(text, &variables), "");
+    }
+
+    #[test]
+    fn nested_blocks() {
+        let variables = variables();
+        let text = "{% if services %}<ul>{% for s in services %}<li>{{ s.name }}\
+                    {% if not s.up %} (down){% endif %}</li>{% endfor %}</ul>{% endif %}";
+        assert_eq!(
+            render(text, &variables),
+            "<ul><li>Mail</li><li>Chat (down)</li></ul>"
+        );
+        let text = "{% for a in services %}{% for b in services %}{{ a.name }}{{ b.name }} \
+                    {% endfor %}{% endfor %}";
+        assert_eq!(
+            render(text, &variables),
+            "MailMail MailChat ChatMail ChatChat "
+        );
+    }
+
+    #[test]
+    fn loop_variables_are_not_names() {
+        let (nodes, _) =
+            Parser::parse("{% for s in services %}{{ s.name }}{{ host }}{% endfor %}").unwrap();
+        let mut names = Vec::new();
+        for node in &nodes {
+            node.names(&mut Vec::new(), &mut names);
+        }
+        assert_eq!(names, vec!["services", "host"]);
+    }
+
+    #[test]
+    fn unclosed_tags() {
+        assert_eq!(error("abc\n  {% if a %}\nyes"), (2, 3));
+        assert_eq!(error("{% for a in b %}{% if a %}{% endfor %}"), (1, 27));
+        assert_eq!(error("<p>\n<p>{{ host\n"), (2, 4));
+        assert_eq!(error("ä {% if a"), (1, 3));
+    }
+
+    #[test]
+    fn invalid_tags() {
+        assert_eq!(error("{% endif %}"), (1, 1));
+        assert_eq!(error("x\n\n    {% else %}"), (3, 5));
+        assert_eq!(error("{% %}"), (1, 1));
+        assert_eq!(error("{% for a of b %}{% endfor %}"), (1, 1));
+        assert_eq!(error("{% if a b %}{% endif %}"), (1, 1));
+        assert_eq!(error("{{ a b }}"), (1, 1));
+        assert_eq!(error("\n {{ a | upper }}"), (2, 2));
+        assert_eq!(error(r#"{{ a | default(none) }}"#), (1, 1));
+    }
+
+    #[test]
+    fn substitute_inserts_literal_text() {
+        let (mut nodes, _) = Parser::parse("{% if a %}{{ js }}{% endif %}{{ js }}").unwrap();
+        for node in &mut nodes {
+            node.substitute("js", "{{ x }}");
+        }
+        let mut variables = Variables::default();
+        variables.insert("a", "1");
+        let mut out = String::new();
+        let mut scope = Scope::new(&variables);
+        for node in &nodes {
+            node.render(&mut scope, &mut out);
+        }
+        assert_eq!(out, "{{ x }}{{ x }}");
+    }
+
+    /// The escaping of the first placeholder in `text`.
+    fn escape_of(text: &str) -> Escape {
+        let (nodes, _) = Parser::parse(text).unwrap();
+        nodes
+            .iter()
+            .find_map(|node| match node {
+                Node::Expr(expr) => Some(expr.escape),
+                _ => None,
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn text_is_html() {
+        assert_eq!(escape_of("<p>{{ host }}</p>"), Escape::Html);
+        assert_eq!(escape_of("{{ host }}"), Escape::Html);
+        assert_eq!(escape_of("a < b {{ host }}"), Escape::Html);
+    }
+
+    #[test]
+    fn attributes() {
+        assert_eq!(escape_of(r#"<a title="{{ host }}">"#), Escape::Attribute);
+        assert_eq!(escape_of("<a title='{{ host }}'>"), Escape::Attribute);
+        assert_eq!(escape_of("<a title={{ host }}>"), Escape::Attribute);
+        assert_eq!(escape_of("<a {{ host }}>"), Escape::Attribute);
+        // A quoted `>` does not end the tag
+        assert_eq!(
+            escape_of(r#"<a title=">" alt={{ host }}>"#),
+            Escape::Attribute
+        );
+        assert_eq!(escape_of(r#"<a title=">">{{ host }}"#), Escape::Html);
+    }
+
+    #[test]
+    fn url_attributes() {
+        assert_eq!(escape_of(r#"<a href="{{ host }}">"#), Escape::Url);
+        assert_eq!(escape_of("<a HREF='{{ host }}'>"), Escape::Url);
+        assert_eq!(escape_of("<img src={{ host }}>"), Escape::Url);
+        assert_eq!(escape_of("<img src = \"{{ host }}\">"), Escape::Url);
+        assert_eq!(escape_of(r#"<a href="java{{ host }}">"#), Escape::Url);
+        assert_eq!(escape_of(r#"<a title="x" href="{{ host }}">"#), Escape::Url);
+        assert_eq!(
+            escape_of(r#"<form method=post action={{ host }}>"#),
+            Escape::Url
+        );
+        // Once the scheme or path has started, the value can not change it
+        assert_eq!(
+            escape_of(r#"<a href="https://{{ host }}/">"#),
+            Escape::Attribute
+        );
+        assert_eq!(
+            escape_of(r#"<a href="/p?q={{ host }}">"#),
+            Escape::Attribute
+        );
+        // Other attributes, and URL attribute names inside other values
+        assert_eq!(
+            escape_of(r#"<a title="href=" data-x="{{ host }}">"#),
+            Escape::Attribute
+        );
+        assert_eq!(
+            escape_of(r#"<a href=x title="{{ host }}">"#),
+            Escape::Attribute
+        );
+        assert_eq!(escape_of(r#"<a href="" {{ host }}>"#), Escape::Attribute);
+    }
+
+    #[test]
+    fn comments() {
+        assert_eq!(escape_of("<!-- {{ host }} -->"), Escape::Html);
+        // A tag inside a comment is not a tag
+        assert_eq!(escape_of("<!-- <a href=' -->{{ host }}"), Escape::Html);
+        assert_eq!(escape_of("<!-- <script> -->{{ host }}"), Escape::Html);
+    }
+
+    #[test]
+    fn script_and_style() {
+        assert_eq!(
+            escape_of("<script>var a = {{ host }};</script>"),
+            Escape::Script
+        );
+        assert_eq!(
+            escape_of("<SCRIPT type=\"module\">{{ host }}"),
+            Escape::Script
+        );
+        assert_eq!(
+            escape_of("<style>p { color: {{ host }} }</style>"),
+            Escape::Style
+        );
+        // Markup inside a script block does not change the context
+        assert_eq!(
+            escape_of("<script>'<a href=\"'; {{ host }}"),
+            Escape::Script
+        );
+        assert_eq!(escape_of("<script>x</script>{{ host }}"), Escape::Html);
+        assert_eq!(escape_of("<script>x</SCRIPT >{{ host }}"), Escape::Html);
+        assert_eq!(escape_of("<style>x</Style><b>{{ host }}</b>"), Escape::Html);
+        // The end of a style block does not end a script block
+        assert_eq!(escape_of("<script></style>{{ host }}"), Escape::Script);
+    }
+
+    #[test]
+    fn raw() {
+        assert_eq!(escape_of("<p>{{ raw host }}</p>"), Escape::Raw);
+        assert_eq!(escape_of("<script>{{ RAW host }}</script>"), Escape::Raw);
+        assert_eq!(escape_of("<a href={{raw host}}>"), Escape::Raw);
+    }
+
+    #[test]
+    fn tags_in_script_blocks_are_reported() {
+        assert!(
+            !Parser::parse("<p>{{ host }}</p><script>x</script>")
+                .unwrap()
+                .1
+        );
+        assert!(Parser::parse("<script>{{ host }}</script>").unwrap().1);
+        assert!(
+            Parser::parse("<style>{% if a %}p{}{% endif %}</style>")
+                .unwrap()
+                .1
+        );
+    }
+}
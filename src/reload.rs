@@ -0,0 +1,95 @@
+use crate::errors::GenericError;
+use crate::site::{Site, SiteHandle};
+use log::{error, info, warn};
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+use tokio::signal::unix::{signal, SignalKind};
+
+/// How often files that are not part of the site, like the tls certificate
+/// and the cached static files, are checked for changes.
+pub const POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+pub type Fingerprint = Vec<(PathBuf, Option<SystemTime>, u64)>;
+
+/// Rebuild the site on SIGHUP, and whenever any of its files change if
+/// `interval` is given. If the new site fails to load, the old one keeps
+/// being served.
+pub async fn watch<F>(
+    handle: SiteHandle,
+    extra_paths: Vec<PathBuf>,
+    interval: Option<Duration>,
+    load: F,
+) where
+    F: Fn() -> Result<Site, GenericError>,
+{
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(hangup) => hangup,
+        Err(e) => {
+            warn!("Unable to listen for SIGHUP, reloading is disabled: {}", e);
+            return;
+        }
+    };
+    let paths = |site: &Site| {
+        let mut paths = site.watched_paths();
+        paths.extend(extra_paths.iter().cloned());
+        paths
+    };
+    let mut previous = fingerprint(&paths(&handle.current()));
+
+    loop {
+        let poll = async {
+            match interval {
+                Some(interval) => tokio::time::sleep(interval).await,
+                None => std::future::pending().await,
+            }
+        };
+        tokio::select! {
+            _ = hangup.recv() => info!("Received SIGHUP, reloading"),
+            _ = poll => {
+                let current = fingerprint(&paths(&handle.current()));
+                if current == previous {
+                    continue;
+                }
+                previous = current;
+                info!("Files changed, reloading");
+            }
+        }
+
+        match load() {
+            Ok(site) => {
+                previous = fingerprint(&paths(&site));
+                handle.replace(site);
+                info!("Reloaded successfully");
+            }
+            Err(e) => {
+                error!("Reload failed, still serving the previous version. {}", e);
+                handle.fail(e.to_string());
+            }
+        }
+    }
+}
+
+/// Modification times and sizes of the given files, and of every file in the
+/// given directories.
+pub fn fingerprint(paths: &[PathBuf]) -> Fingerprint {
+    let mut fingerprint = Vec::new();
+    let mut queue = paths.to_vec();
+    while let Some(path) = queue.pop() {
+        match fs::metadata(&path) {
+            Ok(metadata) if metadata.is_dir() => {
+                if let Ok(entries) = fs::read_dir(&path) {
+                    queue.extend(
+                        entries
+                            .filter_map(|entry| entry.ok())
+                            .map(|entry| entry.path()),
+                    );
+                }
+            }
+            Ok(metadata) => fingerprint.push((path, metadata.modified().ok(), metadata.len())),
+            Err(_) => fingerprint.push((path, None, 0)),
+        }
+    }
+    fingerprint.sort();
+    fingerprint
+}
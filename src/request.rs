@@ -0,0 +1,278 @@
+use crate::server::RemoteAddr;
+use crate::variables::Variables;
+use std::convert::Infallible;
+use std::net::{IpAddr, SocketAddr};
+use warp::http::header::HeaderMap;
+use warp::http::{Method, Uri, Version};
+use warp::path::FullPath;
+use warp::Filter;
+
+/// Variables that are filled for every request, in addition to the
+/// namespaced ones listed in `PROVIDED_NAMESPACES`.
+pub const PROVIDED_VARIABLES: &[&str] = &[
+    "host",
+    "user-agent",
+    "request.path",
+    "request.method",
+    "request.query",
+    "client.ip",
+];
+
+/// Prefixes of variables whose exact names depend on the request.
+pub const PROVIDED_NAMESPACES: &[&str] = &["header.", "request.query.", "env.", "window."];
+
+pub fn is_provided(name: &str) -> bool {
+    PROVIDED_VARIABLES.contains(&name)
+        || PROVIDED_NAMESPACES
+            .iter()
+            .any(|namespace| name.starts_with(namespace))
+}
+
+/// The parts of a request that are available to the template.
+pub struct Request {
+    method: Method,
+    path: String,
+    query: String,
+    headers: HeaderMap,
+    remote: Option<SocketAddr>,
+    version: Version,
+}
+
+pub fn request() -> impl Filter<Extract = (Request,), Error = Infallible> + Clone {
+    let query = warp::query::raw().or(warp::any().map(String::new)).unify();
+
+    warp::method()
+        .and(warp::path::full())
+        .and(query)
+        .and(warp::header::headers_cloned())
+        .and(warp::ext::optional::<RemoteAddr>())
+        .and(warp::ext::optional::<Version>())
+        .map(
+            |method: Method,
+             path: FullPath,
+             query: String,
+             headers: HeaderMap,
+             remote: Option<RemoteAddr>,
+             version: Option<Version>| Request {
+                method,
+                path: path.as_str().to_owned(),
+                query,
+                headers,
+                remote: remote.map(|remote| remote.0),
+                version: version.unwrap_or_default(),
+            },
+        )
+}
+
+impl Request {
+    /// A GET request for `uri` that did not come over the network, for
+    /// rendering the page outside of the server.
+    pub fn new(uri: &Uri, headers: HeaderMap) -> Request {
+        Request {
+            method: Method::GET,
+            path: uri.path().to_owned(),
+            query: uri.query().unwrap_or("").to_owned(),
+            headers,
+            remote: None,
+            version: Version::HTTP_11,
+        }
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name).and_then(|value| value.to_str().ok())
+    }
+
+    pub fn method(&self) -> &str {
+        self.method.as_str()
+    }
+
+    pub fn version(&self) -> Version {
+        self.version
+    }
+
+    /// The address of the client, see `client_ip`.
+    pub fn client_ip(&self, trusted_proxies: &[IpAddr]) -> Option<IpAddr> {
+        client_ip(self.remote, &self.headers, trusted_proxies)
+    }
+
+    pub fn path_and_query(&self) -> String {
+        if self.query.is_empty() {
+            self.path.clone()
+        } else {
+            format!("{}?{}", self.path, self.query)
+        }
+    }
+
+    /// Extracts the template variables from the request. Variables in `base`
+    /// are used for anything the request does not fill.
+    pub fn variables(&self, base: &Variables, trusted_proxies: &[IpAddr]) -> Variables {
+        let mut variables = base.clone();
+
+        for (name, value) in self.headers.iter() {
+            let value = String::from_utf8_lossy(value.as_bytes());
+            variables.insert(format!("header.{}", name.as_str()), value);
+        }
+        for alias in &["host", "user-agent"] {
+            if let Some(value) = self.headers.get(*alias) {
+                variables.insert(*alias, String::from_utf8_lossy(value.as_bytes()));
+            }
+        }
+
+        variables.insert("request.method", self.method.as_str());
+        variables.insert("request.path", self.path.as_str());
+        // Template names are lowercase, so the keys are too
+        for (key, value) in form_urlencoded::parse(self.query.as_bytes()) {
+            variables.insert(format!("request.query.{}", key.to_lowercase()), value);
+        }
+        variables.insert("request.query", self.query.as_str());
+
+        if let Some(ip) = self.client_ip(trusted_proxies) {
+            variables.insert("client.ip", ip.to_string());
+        }
+
+        variables
+    }
+}
+
+/// The address of the client. `X-Forwarded-For` is only honoured when the
+/// connection comes from a trusted proxy, and is read from right to left
+/// until the first untrusted address.
+fn client_ip(
+    remote: Option<SocketAddr>,
+    headers: &HeaderMap,
+    trusted_proxies: &[IpAddr],
+) -> Option<IpAddr> {
+    let mut client = remote?.ip();
+    if !trusted_proxies.contains(&client) {
+        return Some(client);
+    }
+
+    let forwarded = headers
+        .get_all("X-Forwarded-For")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .collect::<Vec<_>>();
+    for addr in forwarded.iter().rev() {
+        match addr.trim().parse::<IpAddr>() {
+            Ok(ip) => {
+                client = ip;
+                if !trusted_proxies.contains(&ip) {
+                    break;
+                }
+            }
+            Err(_) => break,
+        }
+    }
+    Some(client)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::variables::Value;
+    use warp::http::header::HeaderValue;
+
+    fn ips(list: &[&str]) -> Vec<IpAddr> {
+        list.iter().map(|ip| ip.parse().unwrap()).collect()
+    }
+
+    fn client(remote: &str, forwarded: &[&str], trusted: &[&str]) -> Option<IpAddr> {
+        let mut headers = HeaderMap::new();
+        for value in forwarded {
+            headers.append("X-Forwarded-For", HeaderValue::from_str(value).unwrap());
+        }
+        let remote = SocketAddr::new(remote.parse().unwrap(), 40000);
+        client_ip(Some(remote), &headers, &ips(trusted))
+    }
+
+    fn ip(ip: &str) -> Option<IpAddr> {
+        Some(ip.parse().unwrap())
+    }
+
+    #[test]
+    fn untrusted_remote_ignores_forwarded_for() {
+        assert_eq!(
+            client("203.0.113.9", &["198.51.100.1"], &[]),
+            ip("203.0.113.9")
+        );
+        assert_eq!(
+            client("203.0.113.9", &["198.51.100.1"], &["10.0.0.1"]),
+            ip("203.0.113.9")
+        );
+        assert_eq!(client_ip(None, &HeaderMap::new(), &[]), None);
+    }
+
+    #[test]
+    fn trusted_hops_are_skipped() {
+        let trusted = ["10.0.0.1", "10.0.0.2", "::1"];
+        assert_eq!(client("10.0.0.1", &[], &trusted), ip("10.0.0.1"));
+        assert_eq!(
+            client("10.0.0.1", &["198.51.100.1, 10.0.0.2"], &trusted),
+            ip("198.51.100.1")
+        );
+        assert_eq!(
+            client("::1", &["198.51.100.1,10.0.0.2 , 10.0.0.1"], &trusted),
+            ip("198.51.100.1")
+        );
+        // Every hop is trusted, so the leftmost one is the client
+        assert_eq!(
+            client("10.0.0.1", &["10.0.0.2, 10.0.0.1"], &trusted),
+            ip("10.0.0.2")
+        );
+    }
+
+    #[test]
+    fn stops_at_the_first_untrusted_address() {
+        assert_eq!(
+            client(
+                "10.0.0.1",
+                &["192.0.2.66, 198.51.100.1, 10.0.0.2"],
+                &["10.0.0.1", "10.0.0.2"]
+            ),
+            ip("198.51.100.1")
+        );
+    }
+
+    #[test]
+    fn invalid_entries_stop_the_walk() {
+        let trusted = ["10.0.0.1", "10.0.0.2"];
+        assert_eq!(
+            client("10.0.0.1", &["198.51.100.1, unknown, 10.0.0.2"], &trusted),
+            ip("10.0.0.2")
+        );
+        assert_eq!(client("10.0.0.1", &["garbage"], &trusted), ip("10.0.0.1"));
+    }
+
+    #[test]
+    fn several_forwarded_for_headers() {
+        let trusted = ["10.0.0.1", "10.0.0.2", "10.0.0.3"];
+        assert_eq!(
+            client(
+                "10.0.0.1",
+                &["192.0.2.66, 198.51.100.1", "10.0.0.3", "10.0.0.2"],
+                &trusted
+            ),
+            ip("198.51.100.1")
+        );
+    }
+
+    #[tokio::test]
+    async fn query_keys_are_lowercase() {
+        let request = warp::test::request()
+            .path("/page?Foo=x&bar=%C3%A9")
+            .filter(&request())
+            .await
+            .unwrap();
+        let variables = request.variables(&Variables::default(), &[]);
+        let text = |name| match variables.get(name) {
+            Some(Value::Text(text)) => Some(text.clone()),
+            _ => None,
+        };
+        assert_eq!(text("request.query.foo"), Some("x".to_owned()));
+        assert_eq!(text("request.query.Foo"), None);
+        assert_eq!(text("request.query.bar"), Some("é".to_owned()));
+        assert_eq!(text("request.query"), Some("Foo=x&bar=%C3%A9".to_owned()));
+        assert_eq!(text("request.path"), Some("/page".to_owned()));
+    }
+}
@@ -0,0 +1,437 @@
+use crate::errors::GenericError;
+use crate::variables::{Value, Variables};
+use serde::de::{self, Deserialize, Deserializer};
+use serde::{Serialize, Serializer};
+use serde_derive::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use warp::http::header::HeaderValue;
+use warp::http::StatusCode;
+
+/// Source of the current time, so that the schedule can be evaluated against
+/// something other than the system clock.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> SystemTime;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// A `[[schedule]]` entry of the config.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct WindowConfig {
+    pub start: Timestamp,
+    pub end: Timestamp,
+    pub status: Option<u16>,
+    pub html: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub variables: HashMap<String, Value>,
+}
+
+/// A planned maintenance window.
+pub struct Window {
+    pub start: SystemTime,
+    pub end: SystemTime,
+    pub status: StatusCode,
+    pub retry_after: HeaderValue,
+    variables: Variables,
+}
+
+impl Window {
+    pub fn new(config: &WindowConfig) -> Result<Window, GenericError> {
+        if config.end.0 <= config.start.0 {
+            Err("schedule window must end after it starts")?
+        }
+        let status = match StatusCode::from_u16(config.status.unwrap_or(503)) {
+            Ok(status) => status,
+            Err(_) => Err("invalid status code in schedule window")?,
+        };
+        Ok(Window {
+            start: config.start.0,
+            end: config.end.0,
+            status,
+            retry_after: HeaderValue::from_str(&httpdate::fmt_http_date(config.end.0)).unwrap(),
+            variables: Variables::from(config.variables.clone()),
+        })
+    }
+
+    /// Add the `window.*` variables and the window's own variables.
+    pub fn fill(&self, variables: &mut Variables, now: SystemTime) {
+        let remaining = self.end.duration_since(now).unwrap_or_default();
+        variables.insert("window.start", httpdate::fmt_http_date(self.start));
+        variables.insert("window.end", httpdate::fmt_http_date(self.end));
+        variables.insert("window.remaining", format_duration(remaining));
+        variables.extend(&self.variables);
+    }
+}
+
+pub struct Schedule {
+    windows: Vec<Window>,
+    clock: Arc<dyn Clock>,
+}
+
+impl Schedule {
+    pub fn new(windows: Vec<Window>, clock: Arc<dyn Clock>) -> Schedule {
+        Schedule { windows, clock }
+    }
+
+    pub fn now(&self) -> SystemTime {
+        self.clock.now()
+    }
+
+    pub fn windows(&self) -> &[Window] {
+        &self.windows
+    }
+
+    /// Whether any window has a variable called `name`.
+    pub fn provides(&self, name: &str) -> bool {
+        self.windows
+            .iter()
+            .any(|window| window.variables.get(name).is_some())
+    }
+
+    /// The window `now` falls in, along with its index.
+    pub fn active(&self, now: SystemTime) -> Option<(usize, &Window)> {
+        self.windows
+            .iter()
+            .enumerate()
+            .find(|(_, window)| window.start <= now && now < window.end)
+    }
+}
+
+/// Human readable duration, eg. `2h 5m`.
+fn format_duration(duration: Duration) -> String {
+    // Round up, so that the last minute shows as `1m` instead of `0m`
+    let minutes = (duration + Duration::from_secs(59)).as_secs() / 60;
+    let (days, hours, minutes) = (minutes / 1440, minutes / 60 % 24, minutes % 60);
+    let mut parts = Vec::new();
+    if days > 0 {
+        parts.push(format!("{}d", days));
+    }
+    if hours > 0 {
+        parts.push(format!("{}h", hours));
+    }
+    if minutes > 0 || parts.is_empty() {
+        parts.push(format!("{}m", minutes));
+    }
+    parts.join(" ")
+}
+
+/// A point in time given in the config as a TOML datetime or an RFC 3339
+/// string, eg. `2021-03-01T22:00:00+02:00`.
+#[derive(Debug, Clone, Copy)]
+pub struct Timestamp(pub SystemTime);
+
+impl Serialize for Timestamp {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&rfc3339_time(self.0))
+    }
+}
+
+impl<'de> Deserialize<'de> for Timestamp {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let text = match toml::Value::deserialize(deserializer)? {
+            toml::Value::Datetime(datetime) => datetime.to_string(),
+            toml::Value::String(text) => text,
+            _ => return Err(de::Error::custom("expected a datetime")),
+        };
+        parse_rfc3339(&text).map(Timestamp).ok_or_else(|| {
+            de::Error::custom(format!(
+                "invalid datetime `{}`, expected eg. `2021-03-01T22:00:00Z`",
+                text
+            ))
+        })
+    }
+}
+
+fn parse_rfc3339(text: &str) -> Option<SystemTime> {
+    let text = text.trim();
+    let number = |text: &str, range: std::ops::Range<usize>| -> Option<i64> {
+        let part = text.get(range)?;
+        if part.bytes().all(|b| b.is_ascii_digit()) {
+            part.parse().ok()
+        } else {
+            None
+        }
+    };
+    let separators = text.as_bytes();
+    if separators.len() < 20
+        || separators[4] != b'-'
+        || separators[7] != b'-'
+        || !matches!(separators[10], b'T' | b't' | b' ')
+        || separators[13] != b':'
+        || separators[16] != b':'
+    {
+        return None;
+    }
+    let (year, month, day) = (
+        number(text, 0..4)?,
+        number(text, 5..7)?,
+        number(text, 8..10)?,
+    );
+    let (hour, minute, second) = (
+        number(text, 11..13)?,
+        number(text, 14..16)?,
+        number(text, 17..19)?,
+    );
+    if !(1..=12).contains(&month)
+        || day < 1
+        || day > days_in_month(year, month)
+        || hour > 23
+        || minute > 59
+        || second > 60
+    {
+        return None;
+    }
+
+    // Fractions of a second are ignored
+    let mut rest = &text[19..];
+    if let Some(fraction) = rest.strip_prefix('.') {
+        rest = fraction.trim_start_matches(|c: char| c.is_ascii_digit());
+    }
+    let offset = match rest {
+        "Z" | "z" => 0,
+        _ => {
+            let sign = match rest.get(..1)? {
+                "+" => 1,
+                "-" => -1,
+                _ => return None,
+            };
+            // Checked by bytes, as the rest may be any text
+            if rest.len() != 6 || rest.as_bytes()[3] != b':' {
+                return None;
+            }
+            let (hours, minutes) = (number(rest, 1..3)?, number(rest, 4..6)?);
+            if hours > 23 || minutes > 59 {
+                return None;
+            }
+            sign * (hours * 3600 + minutes * 60)
+        }
+    };
+
+    let days = days_from_civil(year, month, day);
+    let seconds = days * 86400 + hour * 3600 + minute * 60 + second - offset;
+    if seconds < 0 {
+        return None;
+    }
+    Some(UNIX_EPOCH + Duration::from_secs(seconds as u64))
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Days since 1970-01-01 of a date in the proleptic Gregorian calendar.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = if year >= 0 { year } else { year - 399 } / 400;
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// The date `days` since 1970-01-01, as year, month and day. The inverse of
+/// `days_from_civil`.
+pub fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = if days >= 0 { days } else { days - 146096 } / 146097;
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// Time as in RFC 3339, eg. `2000-10-10T13:55:36Z`.
+pub fn rfc3339_time(time: SystemTime) -> String {
+    let (year, month, day, hour, minute, second) = utc(time);
+    format!(
+        "{}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year, month, day, hour, minute, second
+    )
+}
+
+/// The UTC date and time of day as year, month, day, hour, minute and second.
+pub fn utc(time: SystemTime) -> (i64, i64, i64, i64, i64, i64) {
+    let seconds = time
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs() as i64)
+        .unwrap_or(0);
+    let (year, month, day) = civil_from_days(seconds / 86400);
+    let seconds = seconds % 86400;
+    (
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct FixedClock(SystemTime);
+
+    impl Clock for FixedClock {
+        fn now(&self) -> SystemTime {
+            self.0
+        }
+    }
+
+    fn at(seconds: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(seconds)
+    }
+
+    fn window(start: u64, end: u64) -> Window {
+        let mut variables = HashMap::new();
+        variables.insert("reason".to_owned(), Value::from("upgrade"));
+        Window::new(&WindowConfig {
+            start: Timestamp(at(start)),
+            end: Timestamp(at(end)),
+            status: None,
+            html: None,
+            variables,
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn rfc3339() {
+        assert_eq!(parse_rfc3339("1970-01-01T00:00:00Z"), Some(UNIX_EPOCH));
+        assert_eq!(
+            parse_rfc3339("2021-03-01T22:00:00+02:00"),
+            Some(at(1614628800))
+        );
+        assert_eq!(
+            parse_rfc3339("2021-03-01 19:30:00.123-00:30"),
+            Some(at(1614628800))
+        );
+        assert_eq!(
+            parse_rfc3339(" 2021-03-01t20:00:00z "),
+            Some(at(1614628800))
+        );
+        assert_eq!(
+            parse_rfc3339("2024-02-29T00:00:00Z"),
+            Some(at(19782 * 86400))
+        );
+        assert_eq!(
+            parse_rfc3339("2000-02-29T00:00:00Z"),
+            Some(at(11016 * 86400))
+        );
+        // A leap second is accepted and rolls over
+        assert_eq!(parse_rfc3339("2016-12-31T23:59:60Z"), Some(at(1483228800)));
+
+        for invalid in &[
+            "2021-03-01T22:00:00",
+            "2021-03-01",
+            "2021-3-01T22:00:00Z",
+            "2021-13-01T22:00:00Z",
+            "2021-03-01T24:00:00Z",
+            "2021-03-01T22:00:00+0200",
+            "2021-03-01T22:00:00 UTC",
+            "1969-12-31T23:59:59Z",
+            "2024-02-30T00:00:00Z",
+            "2023-02-29T00:00:00Z",
+            "1900-02-29T00:00:00Z",
+            "2021-04-31T00:00:00Z",
+            "2021-03-00T00:00:00Z",
+            "2021-03-01T22:00:00+2é:0",
+            "2021-03-01T22:00:00+é:000",
+            "2021-03-01T22:00:00+02é0",
+            "2021-03-01T22:00:00++1:00",
+            "2021-03-01T22:00:00+24:00",
+            "2021-03-01T22:00:00é",
+            "2021-03-01T22:00:00.5é",
+            "2021-03-é1T22:00:00Z",
+        ] {
+            assert_eq!(parse_rfc3339(invalid), None, "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn civil_dates() {
+        assert_eq!(days_from_civil(1970, 1, 1), 0);
+        assert_eq!(days_from_civil(1969, 12, 31), -1);
+        assert_eq!(days_from_civil(2000, 3, 1), 11017);
+        assert_eq!(days_from_civil(2024, 2, 29), 19782);
+        assert_eq!(days_from_civil(1600, 1, 1), -135140);
+        assert_eq!(days_from_civil(2400, 12, 31), 157419);
+        for days in (-150000..150000).step_by(7) {
+            let (year, month, day) = civil_from_days(days);
+            assert_eq!(days_from_civil(year, month, day), days);
+        }
+        assert_eq!(rfc3339_time(at(1614628800)), "2021-03-01T20:00:00Z");
+    }
+
+    #[test]
+    fn active_window() {
+        let clock = Arc::new(FixedClock(at(1500)));
+        let schedule = Schedule::new(vec![window(1000, 2000), window(3000, 4000)], clock);
+        let active = |seconds| schedule.active(at(seconds)).map(|(i, _)| i);
+        assert_eq!(schedule.active(schedule.now()).map(|(i, _)| i), Some(0));
+        assert_eq!(active(999), None);
+        assert_eq!(active(1000), Some(0));
+        assert_eq!(active(1999), Some(0));
+        assert_eq!(active(2000), None);
+        assert_eq!(active(3500), Some(1));
+        assert_eq!(active(4000), None);
+        assert!(schedule.provides("reason"));
+        assert!(!schedule.provides("window.start"));
+    }
+
+    #[test]
+    fn fill() {
+        let window = window(0, 2 * 86400 + 3 * 3600 + 5 * 60);
+        let remaining = |seconds| {
+            let mut variables = Variables::default();
+            window.fill(&mut variables, at(seconds));
+            match variables.get("window.remaining") {
+                Some(Value::Text(text)) => text.clone(),
+                _ => panic!("window.remaining is not set"),
+            }
+        };
+        assert_eq!(remaining(0), "2d 3h 5m");
+        assert_eq!(remaining(86400), "1d 3h 5m");
+        assert_eq!(remaining(2 * 86400 + 3 * 3600), "5m");
+        // The last seconds round up to a minute
+        assert_eq!(remaining(2 * 86400 + 3 * 3600 + 5 * 60 - 1), "1m");
+        assert_eq!(remaining(2 * 86400 + 3 * 3600 + 4 * 60 + 1), "1m");
+        assert_eq!(remaining(2 * 86400 + 3 * 3600 + 3 * 60 + 59), "2m");
+        assert_eq!(remaining(3 * 86400), "0m");
+
+        let mut variables = Variables::default();
+        window.fill(&mut variables, at(0));
+        let text = |name| match variables.get(name) {
+            Some(Value::Text(text)) => text.as_str(),
+            _ => "",
+        };
+        assert_eq!(text("window.start"), "Thu, 01 Jan 1970 00:00:00 GMT");
+        assert_eq!(text("window.end"), "Sat, 03 Jan 1970 03:05:00 GMT");
+        assert_eq!(text("reason"), "upgrade");
+    }
+}
@@ -0,0 +1,171 @@
+use crate::tls;
+use log::error;
+use std::convert::Infallible;
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::{TcpListener, TcpStream, UnixListener, UnixStream};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio_rustls::TlsAcceptor;
+use warp::filters::BoxedFilter;
+use warp::hyper::server::accept;
+use warp::hyper::service::{make_service_fn, service_fn, Service};
+use warp::hyper::{self, Body};
+use warp::reply::Reply;
+
+/// The routes served on a listener.
+pub type Routes = BoxedFilter<(Box<dyn Reply>,)>;
+
+/// Address of the client, added to the request extensions since warp can
+/// not see it through a custom listener.
+#[derive(Clone, Copy)]
+pub struct RemoteAddr(pub SocketAddr);
+
+/// An accepted connection, along with the address of the client if it has
+/// one.
+pub struct Connection<S> {
+    pub stream: S,
+    pub remote: Option<SocketAddr>,
+}
+
+impl<S> Connection<S> {
+    pub fn new(stream: S, remote: Option<SocketAddr>) -> Connection<S> {
+        Connection { stream, remote }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Connection<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_read(cx, buf)
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Connection<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.stream).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_shutdown(cx)
+    }
+}
+
+/// Accept connections from `listener` until the returned receiver is
+/// dropped.
+pub fn accept_tcp(listener: TcpListener) -> mpsc::Receiver<Connection<TcpStream>> {
+    let (sender, receiver) = mpsc::channel(32);
+    tokio::spawn(async move {
+        loop {
+            tokio::select! {
+                accepted = listener.accept() => match accepted {
+                    Ok((stream, remote)) => {
+                        let _ = stream.set_nodelay(true);
+                        if sender.send(Connection::new(stream, Some(remote))).await.is_err() {
+                            return;
+                        }
+                    }
+                    Err(e) => accept_error(e).await,
+                },
+                _ = sender.closed() => return,
+            }
+        }
+    });
+    receiver
+}
+
+/// Accept connections from `listener` until the returned receiver is
+/// dropped.
+pub fn accept_unix(listener: UnixListener) -> mpsc::Receiver<Connection<UnixStream>> {
+    let (sender, receiver) = mpsc::channel(32);
+    tokio::spawn(async move {
+        loop {
+            tokio::select! {
+                accepted = listener.accept() => match accepted {
+                    Ok((stream, _)) => {
+                        if sender.send(Connection::new(stream, None)).await.is_err() {
+                            return;
+                        }
+                    }
+                    Err(e) => accept_error(e).await,
+                },
+                _ = sender.closed() => return,
+            }
+        }
+    });
+    receiver
+}
+
+/// Print an error from accepting a connection, and give the system a moment
+/// to recover if it ran out of file descriptors or the like.
+pub async fn accept_error(e: io::Error) {
+    error!("Unable to accept a connection: {}", e);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+}
+
+/// Serve `routes` on the connections from `incoming` until `signal`
+/// resolves and the open connections finish.
+pub async fn serve<S, F>(routes: Routes, mut incoming: mpsc::Receiver<Connection<S>>, signal: F)
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    F: Future<Output = ()>,
+{
+    let make_service = make_service_fn(move |connection: &Connection<S>| {
+        let remote = connection.remote;
+        let routes = routes.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |mut request: hyper::Request<Body>| {
+                if let Some(remote) = remote {
+                    request.extensions_mut().insert(RemoteAddr(remote));
+                }
+                let version = request.version();
+                request.extensions_mut().insert(version);
+                warp::service(routes.clone()).call(request)
+            }))
+        }
+    });
+    let incoming = accept::poll_fn(move |cx| {
+        incoming
+            .poll_recv(cx)
+            .map(|connection| connection.map(Ok::<_, Infallible>))
+    });
+    let server = hyper::Server::builder(incoming)
+        .serve(make_service)
+        .with_graceful_shutdown(signal);
+    if let Err(e) = server.await {
+        error!("Server error: {}", e);
+    }
+}
+
+/// Serve `routes` on a new task, over tls if `acceptor` is given.
+pub fn spawn<S, F>(
+    routes: Routes,
+    incoming: mpsc::Receiver<Connection<S>>,
+    acceptor: Option<TlsAcceptor>,
+    signal: F,
+) -> JoinHandle<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    F: Future<Output = ()> + Send + 'static,
+{
+    match acceptor {
+        Some(acceptor) => tokio::spawn(serve(routes, tls::accept(incoming, acceptor), signal)),
+        None => tokio::spawn(serve(routes, incoming, signal)),
+    }
+}
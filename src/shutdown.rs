@@ -0,0 +1,94 @@
+use log::{info, warn};
+use std::future::Future;
+use std::time::{Duration, Instant};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::watch;
+
+/// Tracks whether the server has been asked to shut down with SIGTERM or
+/// SIGINT.
+#[derive(Clone)]
+pub struct Shutdown {
+    receiver: watch::Receiver<bool>,
+}
+
+impl Shutdown {
+    /// Start listening for the shutdown signals.
+    pub fn listen() -> Shutdown {
+        let (sender, receiver) = watch::channel(false);
+        tokio::spawn(async move {
+            let mut terminate = match signal(SignalKind::terminate()) {
+                Ok(terminate) => terminate,
+                Err(e) => {
+                    warn!("Unable to listen for SIGTERM: {}", e);
+                    return;
+                }
+            };
+            tokio::select! {
+                _ = terminate.recv() => info!("Received SIGTERM"),
+                _ = tokio::signal::ctrl_c() => info!("Received SIGINT"),
+            }
+            let _ = sender.send(true);
+        });
+        Shutdown { receiver }
+    }
+
+    /// Resolves once shutdown has been requested.
+    pub async fn wait(mut self) {
+        while !*self.receiver.borrow() {
+            if self.receiver.changed().await.is_err() {
+                // The signal listener is gone, so shutdown will never be requested
+                std::future::pending::<()>().await;
+            }
+        }
+    }
+
+    /// Run `server` until it finishes by itself, or until shutdown is
+    /// requested and either the open connections finish or `timeout` passes.
+    pub async fn drain<F: Future<Output = ()>>(&self, server: F, timeout: Duration) {
+        tokio::pin!(server);
+        let finished = tokio::select! {
+            _ = &mut server => true,
+            _ = self.clone().wait() => false,
+        };
+        // The server may notice the shutdown first, if it has nothing left to
+        // wait for
+        if finished && !*self.receiver.borrow() {
+            return;
+        }
+
+        info!(
+            "No longer accepting connections, waiting up to {}s for open connections to finish",
+            timeout.as_secs()
+        );
+        let start = Instant::now();
+        if finished {
+            info!("All connections finished in 0.0s");
+            return;
+        }
+        let deadline = tokio::time::sleep(timeout);
+        tokio::pin!(deadline);
+        let mut progress = tokio::time::interval(Duration::from_secs(1));
+        progress.tick().await;
+        loop {
+            tokio::select! {
+                _ = &mut server => {
+                    info!(
+                        "All connections finished in {:.1}s",
+                        start.elapsed().as_secs_f32()
+                    );
+                    return;
+                }
+                _ = &mut deadline => {
+                    warn!("Drain timeout reached, closing the remaining connections");
+                    return;
+                }
+                _ = progress.tick() => {
+                    info!(
+                        "Still waiting for open connections ({}s)",
+                        start.elapsed().as_secs()
+                    );
+                }
+            }
+        }
+    }
+}
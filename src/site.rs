@@ -0,0 +1,237 @@
+use crate::cache::Cache;
+use crate::cache_control::CachePolicy;
+use crate::conditional::Validators;
+use crate::config::Config;
+use crate::errors::{GenericError, PathError};
+use crate::files::{Mimetypes, Symlinks};
+use crate::page::Page;
+use crate::request::{self, Request};
+use crate::schedule::{Clock, Schedule, Window};
+use crate::variables::Variables;
+use log::warn;
+use std::env;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use warp::http::header::HeaderValue;
+use warp::http::StatusCode;
+use warp::reply::Response;
+
+/// Everything that is built from the config and the served files.
+pub struct Site {
+    pub config: Config,
+    pub mimetypes: Mimetypes,
+    /// Canonicalized `static_content` path.
+    pub content_path: Option<PathBuf>,
+    pub symlinks: Symlinks,
+    /// The static content in memory, if `static_cache_size` is set.
+    pub cache: Option<Cache>,
+    /// The `Cache-Control` headers, from the `[cache]` table.
+    pub cache_control: Arc<CachePolicy>,
+    main_page: Arc<Page>,
+    window_pages: Vec<Arc<Page>>,
+    /// Validators of the pages that render the same for every request.
+    main_validators: Option<Validators>,
+    window_validators: Vec<Option<Validators>>,
+    schedule: Schedule,
+    base: Variables,
+    status: StatusCode,
+    retry_after: Option<HeaderValue>,
+    /// How long building the pages took.
+    pub build_time: Duration,
+}
+
+impl Site {
+    pub fn load(config: Config, clock: Arc<dyn Clock>) -> Result<Site, GenericError> {
+        let mimetypes = Mimetypes::try_fetch(&config.mime_types).unwrap_or_default();
+
+        let content_path = match &config.static_content {
+            Some(static_content) => match static_content.canonicalize() {
+                Ok(path) => Some(path),
+                Err(e) => Err(PathError::new(static_content.clone(), e.to_string()))?,
+            },
+            None => None,
+        };
+        if let Some(path) = &content_path {
+            if path.is_file() && config.static_path.trim_matches('/').is_empty() {
+                Err(GenericError::ConfigError(vec![
+                    "static_content must be a directory when static_path is empty".to_owned(),
+                ]))?
+            }
+        }
+        let symlinks = config.static_symlinks.parse()?;
+        let cache_control = Arc::new(CachePolicy::new(&config.cache)?);
+        let cache = match (&content_path, config.static_cache_bytes()) {
+            (Some(path), Some(limit)) if limit > 0 => Some(Cache::load(
+                path,
+                symlinks,
+                &mimetypes,
+                cache_control.clone(),
+                limit,
+            )),
+            _ => None,
+        };
+
+        let start = Instant::now();
+        let main_page = Arc::new(Page::load(&config, &config.html, &cache_control)?);
+
+        let mut windows = Vec::new();
+        let mut window_pages = Vec::new();
+        for window in &config.schedule {
+            windows.push(Window::new(window)?);
+            window_pages.push(match &window.html {
+                Some(html) => Arc::new(Page::load(&config, html, &cache_control)?),
+                None => main_page.clone(),
+            });
+        }
+        let build_time = start.elapsed();
+        let schedule = Schedule::new(windows, clock);
+
+        let placeholders: Vec<&str> = Some(&main_page)
+            .into_iter()
+            .chain(&window_pages)
+            .flat_map(|page| page.template.placeholders())
+            .collect();
+        let mut base = Variables::from(config.defaults.clone());
+        for (key, value) in env::vars() {
+            let name = format!("env.{}", key.to_lowercase());
+            if placeholders.contains(&&*name) {
+                base.insert(name, value);
+            }
+        }
+
+        let mut unfilled: Vec<&str> = placeholders
+            .into_iter()
+            .filter(|name| {
+                !request::is_provided(name) && base.get(name).is_none() && !schedule.provides(name)
+            })
+            .collect();
+        unfilled.sort_unstable();
+        unfilled.dedup();
+        if !unfilled.is_empty() {
+            warn!(
+                "Nothing will fill the following placeholders: {}",
+                unfilled.join(", ")
+            );
+        }
+
+        // Cacheable pages use no request or window.* variables, so the
+        // start of each window renders them like any other moment would
+        let main_validators = main_page.validators(&base);
+        let window_validators = schedule
+            .windows()
+            .iter()
+            .zip(&window_pages)
+            .map(|(window, page)| {
+                let mut variables = base.clone();
+                window.fill(&mut variables, window.start);
+                page.validators(&variables)
+            })
+            .collect();
+
+        let status = match StatusCode::from_u16(config.status) {
+            Ok(status) => status,
+            Err(_) => Err(format!("Invalid status code: {}", config.status))?,
+        };
+        let retry_after = match &config.retry_after {
+            Some(retry_after) => {
+                let retry_after = retry_after.trim();
+                if retry_after.parse::<u64>().is_err()
+                    && httpdate::parse_http_date(retry_after).is_err()
+                {
+                    Err("Invalid Retry-After value, expected seconds or an http date")?
+                }
+                Some(HeaderValue::from_str(retry_after).unwrap())
+            }
+            None => None,
+        };
+
+        Ok(Site {
+            config,
+            mimetypes,
+            content_path,
+            symlinks,
+            cache,
+            cache_control,
+            main_page,
+            window_pages,
+            main_validators,
+            window_validators,
+            schedule,
+            base,
+            status,
+            retry_after,
+            build_time,
+        })
+    }
+
+    pub fn reply(&self, request: &Request) -> Response {
+        let mut variables = request.variables(&self.base, &self.config.trusted_proxies);
+        let now = self.schedule.now();
+        match self.schedule.active(now) {
+            Some((i, window)) => {
+                window.fill(&mut variables, now);
+                self.window_pages[i].reply(
+                    request,
+                    &variables,
+                    window.status,
+                    Some(&window.retry_after),
+                    self.window_validators[i].as_ref(),
+                )
+            }
+            None => self.main_page.reply(
+                request,
+                &variables,
+                self.status,
+                self.retry_after.as_ref(),
+                self.main_validators.as_ref(),
+            ),
+        }
+    }
+
+    /// Files that the site is built from, and should be reloaded when they
+    /// change.
+    pub fn watched_paths(&self) -> Vec<PathBuf> {
+        let mut paths = vec![self.config.mime_types.clone()];
+        for page in Some(&self.main_page).into_iter().chain(&self.window_pages) {
+            paths.extend(page.paths.iter().cloned());
+        }
+        paths
+    }
+}
+
+/// The currently served site, which can be replaced while requests are
+/// being served.
+#[derive(Clone)]
+pub struct SiteHandle {
+    site: Arc<RwLock<Arc<Site>>>,
+    /// Why the last reload failed, if it did.
+    error: Arc<RwLock<Option<String>>>,
+}
+
+impl SiteHandle {
+    pub fn new(site: Site) -> SiteHandle {
+        SiteHandle {
+            site: Arc::new(RwLock::new(Arc::new(site))),
+            error: Arc::new(RwLock::new(None)),
+        }
+    }
+
+    pub fn current(&self) -> Arc<Site> {
+        self.site.read().unwrap().clone()
+    }
+
+    pub fn replace(&self, site: Site) {
+        *self.site.write().unwrap() = Arc::new(site);
+        *self.error.write().unwrap() = None;
+    }
+
+    /// Record that reloading failed, and the current site is out of date.
+    pub fn fail(&self, error: String) {
+        *self.error.write().unwrap() = Some(error);
+    }
+
+    pub fn error(&self) -> Option<String> {
+        self.error.read().unwrap().clone()
+    }
+}
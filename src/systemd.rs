@@ -0,0 +1,296 @@
+use crate::listen::Listener;
+use std::env;
+use std::io;
+use std::mem;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::os::unix::net::UnixDatagram;
+use std::process;
+use std::time::Duration;
+
+/// The first file descriptor passed by systemd.
+const LISTEN_FDS_START: RawFd = 3;
+
+/// File descriptors passed by systemd socket activation, or None if the
+/// process was not socket activated. The variables describing them are
+/// removed, so this must run before any other threads are started.
+pub fn activated_fds() -> Option<Vec<RawFd>> {
+    let pid = env::var("LISTEN_PID").ok();
+    let count = env::var("LISTEN_FDS").ok();
+    // The variables are meant for this process only, not for its children
+    env::remove_var("LISTEN_PID");
+    env::remove_var("LISTEN_FDS");
+    env::remove_var("LISTEN_FDNAMES");
+    fds(&pid?, &count?, process::id())
+}
+
+fn fds(pid: &str, count: &str, own_pid: u32) -> Option<Vec<RawFd>> {
+    if pid.parse::<u32>().ok()? != own_pid {
+        return None;
+    }
+    // Without any sockets there is nothing to serve, so bind as usual
+    let count = count.parse::<RawFd>().ok().filter(|count| *count > 0)?;
+    Some((LISTEN_FDS_START..LISTEN_FDS_START + count).collect())
+}
+
+/// Listeners for the file descriptors passed by systemd.
+pub fn listeners(fds: &[RawFd]) -> io::Result<Vec<Listener>> {
+    fds.iter().map(|&fd| listener(fd)).collect()
+}
+
+fn listener(fd: RawFd) -> io::Result<Listener> {
+    unsafe {
+        if libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) == -1 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    let mut socket_type: libc::c_int = 0;
+    let mut length = mem::size_of::<libc::c_int>() as libc::socklen_t;
+    let result = unsafe {
+        libc::getsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            libc::SO_TYPE,
+            &mut socket_type as *mut libc::c_int as *mut libc::c_void,
+            &mut length,
+        )
+    };
+    if result == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    if socket_type != libc::SOCK_STREAM {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("file descriptor {} is not a stream socket", fd),
+        ));
+    }
+
+    let mut address: libc::sockaddr_storage = unsafe { mem::zeroed() };
+    let mut length = mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+    let result = unsafe {
+        libc::getsockname(
+            fd,
+            &mut address as *mut libc::sockaddr_storage as *mut libc::sockaddr,
+            &mut length,
+        )
+    };
+    if result == -1 {
+        return Err(io::Error::last_os_error());
+    }
+
+    match address.ss_family as libc::c_int {
+        libc::AF_INET | libc::AF_INET6 => {
+            let listener = unsafe { std::net::TcpListener::from_raw_fd(fd) };
+            listener.set_nonblocking(true)?;
+            let addr = listener.local_addr()?;
+            Ok(Listener::Tcp(
+                tokio::net::TcpListener::from_std(listener)?,
+                addr,
+            ))
+        }
+        libc::AF_UNIX => {
+            let listener = unsafe { std::os::unix::net::UnixListener::from_raw_fd(fd) };
+            listener.set_nonblocking(true)?;
+            let path = listener
+                .local_addr()?
+                .as_pathname()
+                .map(|path| path.to_owned())
+                .unwrap_or_default();
+            Ok(Listener::Unix(
+                tokio::net::UnixListener::from_std(listener)?,
+                path,
+            ))
+        }
+        family => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "file descriptor {} is not a socket of a known family ({})",
+                fd, family
+            ),
+        )),
+    }
+}
+
+/// Send `state` to the service manager, if running under one. Errors are
+/// ignored, since the service works without the notifications.
+pub fn notify(state: &str) {
+    if let Ok(path) = env::var("NOTIFY_SOCKET") {
+        let _ = send(&path, state);
+    }
+}
+
+/// Send `state` to the datagram socket at `path`, which is an abstract
+/// socket name if it starts with `@`.
+fn send(path: &str, state: &str) -> io::Result<()> {
+    let mut address: libc::sockaddr_un = unsafe { mem::zeroed() };
+    address.sun_family = libc::AF_UNIX as libc::sa_family_t;
+    let path = path.as_bytes();
+    if path.is_empty() || path.len() >= address.sun_path.len() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "invalid notify socket path",
+        ));
+    }
+    for (to, from) in address.sun_path.iter_mut().zip(path) {
+        *to = *from as libc::c_char;
+    }
+    // Abstract names start with a null byte in place of the `@`
+    if path[0] == b'@' {
+        address.sun_path[0] = 0;
+    }
+    let length = mem::size_of::<libc::sa_family_t>() + path.len();
+
+    let socket = UnixDatagram::unbound()?;
+    let result = unsafe {
+        libc::sendto(
+            socket.as_raw_fd(),
+            state.as_ptr() as *const libc::c_void,
+            state.len(),
+            0,
+            &address as *const libc::sockaddr_un as *const libc::sockaddr,
+            length as libc::socklen_t,
+        )
+    };
+    if result == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Interval to send watchdog pings at, if the service manager expects them.
+pub fn watchdog_interval() -> Option<Duration> {
+    if let Ok(pid) = env::var("WATCHDOG_PID") {
+        if pid.parse::<u32>().ok()? != process::id() {
+            return None;
+        }
+    }
+    let usec = env::var("WATCHDOG_USEC").ok()?.parse::<u64>().ok()?;
+    if usec == 0 {
+        return None;
+    }
+    // Ping twice as often as required, so a late ping is not fatal
+    Some(Duration::from_micros(usec / 2))
+}
+
+/// Send a watchdog ping every `interval`.
+pub async fn watchdog(interval: Duration) {
+    let mut interval = tokio::time::interval(interval);
+    loop {
+        interval.tick().await;
+        notify("WATCHDOG=1");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::os::unix::io::IntoRawFd;
+    use std::os::unix::net::UnixListener;
+    use std::path::PathBuf;
+
+    fn temp_path(name: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("simplestatic-{}-{}", name, process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    fn receive(socket: &UnixDatagram) -> String {
+        let mut buffer = [0; 64];
+        let length = socket.recv(&mut buffer).unwrap();
+        String::from_utf8_lossy(&buffer[..length]).into_owned()
+    }
+
+    #[test]
+    fn activated_fds() {
+        assert_eq!(fds("42", "2", 42), Some(vec![3, 4]));
+        assert_eq!(fds("42", "0", 42), None);
+        assert_eq!(fds("42", "-1", 42), None);
+        assert_eq!(fds("41", "2", 42), None);
+        assert_eq!(fds("x", "2", 42), None);
+        assert_eq!(fds("42", "x", 42), None);
+    }
+
+    #[test]
+    fn notify_path() {
+        let path = temp_path("notify");
+        let socket = UnixDatagram::bind(&path).unwrap();
+        send(path.to_str().unwrap(), "READY=1").unwrap();
+        assert_eq!(receive(&socket), "READY=1");
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn notify_abstract() {
+        let name = format!("simplestatic-notify-{}", process::id());
+        let socket = UnixDatagram::unbound().unwrap();
+        let mut address: libc::sockaddr_un = unsafe { mem::zeroed() };
+        address.sun_family = libc::AF_UNIX as libc::sa_family_t;
+        for (to, from) in address.sun_path[1..].iter_mut().zip(name.as_bytes()) {
+            *to = *from as libc::c_char;
+        }
+        let length = mem::size_of::<libc::sa_family_t>() + 1 + name.len();
+        let result = unsafe {
+            libc::bind(
+                socket.as_raw_fd(),
+                &address as *const libc::sockaddr_un as *const libc::sockaddr,
+                length as libc::socklen_t,
+            )
+        };
+        assert_eq!(result, 0, "{}", io::Error::last_os_error());
+
+        send(&format!("@{}", name), "STOPPING=1").unwrap();
+        assert_eq!(receive(&socket), "STOPPING=1");
+    }
+
+    #[test]
+    fn notify_missing_socket() {
+        assert!(send(temp_path("missing").to_str().unwrap(), "READY=1").is_err());
+        assert!(send("", "READY=1").is_err());
+    }
+
+    #[tokio::test]
+    async fn tcp_and_unix_listeners() {
+        let tcp = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let tcp_address = tcp.local_addr().unwrap();
+        let path = temp_path("listener");
+        let unix = UnixListener::bind(&path).unwrap();
+
+        let fds = [tcp.into_raw_fd(), unix.into_raw_fd()];
+        let listeners = listeners(&fds).unwrap();
+        match &listeners[..] {
+            [Listener::Tcp(_, address), Listener::Unix(_, unix_path)] => {
+                assert_eq!(*address, tcp_address);
+                assert_eq!(*unix_path, path);
+            }
+            _ => panic!("unexpected listeners"),
+        }
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn datagram_is_not_a_listener() {
+        let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let error = listeners(&[socket.as_raw_fd()]).err().unwrap();
+        assert!(
+            error.to_string().contains("is not a stream socket"),
+            "{}",
+            error
+        );
+        let path = temp_path("datagram");
+        let socket = UnixDatagram::bind(&path).unwrap();
+        assert!(listeners(&[socket.as_raw_fd()]).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn pipe_is_not_a_listener() {
+        let mut pipe = [0; 2];
+        assert_eq!(unsafe { libc::pipe(pipe.as_mut_ptr()) }, 0);
+        assert!(listeners(&pipe[..1]).is_err());
+        unsafe {
+            libc::close(pipe[0]);
+            libc::close(pipe[1]);
+        }
+    }
+}
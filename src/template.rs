@@ -1,4 +1,6 @@
 use crate::errors::GenericError;
+use crate::parser::{Node, Parser};
+use crate::variables::{Scope, Variables};
 use data_encoding::BASE64;
 use minifier::{css, js};
 use regex::{Captures, Regex};
@@ -6,9 +8,12 @@ use ring::digest;
 
 #[derive(Clone)]
 pub struct Template {
-    text: String,
-    regex: Regex,
+    nodes: Vec<Node>,
+    size_hint: usize,
     pub unsafe_inline: bool,
+    /// Whether the contents of a `<script>` or `<style>` block depend on the
+    /// variables, so their hashes have to be taken from the rendered page.
+    pub dynamic_inline: bool,
 }
 
 impl Template {
@@ -17,6 +22,11 @@ impl Template {
         css: Option<Vec<String>>,
         js: Option<Vec<String>>,
     ) -> Result<(Self, Vec<String>, Vec<String>), GenericError> {
+        let text = text.into();
+        // Check the syntax before minifying, so that errors point at the
+        // right line and column of the html file.
+        Parser::parse(&text)?;
+
         let css = match css {
             Some(c) => Template::create_tags(c, Tag::Style)?,
             None => String::new(),
@@ -28,24 +38,61 @@ impl Template {
 
         let unsafe_inline = js.contains('\n') || css.contains('\n');
 
-        let re = Regex::new(r"\{\{ (?P<item>.*) \}\}")?;
-        let (text, js_hashes, css_hashes) = Template::initialize_text(&re, text.into(), css, js)?;
+        let (text, mut js_hashes, mut css_hashes) = Template::initialize_text(text)?;
+        // The text was checked above, so an error here is in how the script
+        // and style blocks were minified
+        let (mut nodes, dynamic_inline) = Parser::parse(&text)
+            .map_err(|e| format!("Unable to parse the minified page: {}", e))?;
+
+        // The css and js files are embedded as they are, anything looking
+        // like template syntax in them is not parsed
+        let css = Template::minimize(&css, Tag::Style)?;
+        let js = Template::minimize(&js, Tag::Script)?;
+        css_hashes.extend(Template::get_hashes(&css, Tag::Style)?);
+        js_hashes.extend(Template::get_hashes(&js, Tag::Script)?);
+        for node in &mut nodes {
+            node.substitute("css", &css);
+            node.substitute("js", &js);
+        }
+        let size_hint = nodes.iter().map(Node::size_hint).sum();
 
         Ok((
             Template {
-                text: text,
-                regex: re,
+                nodes,
+                size_hint,
                 unsafe_inline,
+                dynamic_inline,
             },
             js_hashes,
             css_hashes,
         ))
     }
 
-    pub fn render(&self, host: String, ua: String) -> String {
-        self.text
-            .replace("{{ host }}", &host)
-            .replace("{{ user-agent }}", &ua)
+    pub fn render(&self, variables: &Variables) -> String {
+        let mut rendered = String::with_capacity(self.size_hint);
+        let mut scope = Scope::new(variables);
+        for node in &self.nodes {
+            node.render(&mut scope, &mut rendered);
+        }
+        rendered
+    }
+
+    /// The Content-Security-Policy hashes of the `<script>` and `<style>`
+    /// blocks of rendered text.
+    pub fn hashes(text: &str) -> (Vec<String>, Vec<String>) {
+        (
+            Template::get_hashes(text, Tag::Script).unwrap_or_default(),
+            Template::get_hashes(text, Tag::Style).unwrap_or_default(),
+        )
+    }
+
+    /// Names of all variables used in the template, lowercased.
+    pub fn placeholders(&self) -> Vec<&str> {
+        let mut names = Vec::new();
+        for node in &self.nodes {
+            node.names(&mut Vec::new(), &mut names);
+        }
+        names
     }
 
     fn create_tags(list: Vec<String>, tag: Tag) -> Result<String, GenericError> {
@@ -57,21 +104,8 @@ impl Template {
     }
 
     fn initialize_text(
-        regex: &Regex,
-        html: String,
-        css: String,
-        js: String,
+        new_text: String,
     ) -> Result<(String, Vec<String>, Vec<String>), GenericError> {
-        // Add CSS and JS to the template text to specified locations
-        let new_text = (*regex.replace_all(&html, |caps: &Captures| {
-            match &*caps["item"].to_lowercase() {
-                "css" => String::from(&css),
-                "js" => String::from(&js),
-                x => format!("{{{{ {} }}}}", x),
-            }
-        }))
-        .to_owned();
-
         // Force the contents to be CRLF (it's a HTML standard thing)
         // Required so the digest is correct.
         /*
@@ -109,23 +143,23 @@ impl Template {
         Ok((new_text, js_hashes, css_hashes))
     }
 
-    fn minimize(text: &String, tag: Tag) -> Result<String, GenericError> {
+    fn minimize(text: &str, tag: Tag) -> Result<String, GenericError> {
         let regex = tag.as_regex()?;
-        let text = (*regex.replace_all(&text.clone(), |caps: &Captures| {
+        let text = (*regex.replace_all(text, |caps: &Captures| {
             let content = match tag {
                 Tag::Script => js::minify(&caps["content"]),
-                Tag::Style => css::minify(&caps["content"]).unwrap_or("ERR".to_owned()),
+                Tag::Style => css::minify(&caps["content"]).unwrap_or_else(|_| "ERR".to_owned()),
             };
-            return format!("<{}>{}</{}>", tag.as_str(), content, tag.as_str());
+            format!("<{}>{}</{}>", tag.as_str(), content, tag.as_str())
         }))
         .to_owned();
         Ok(text)
     }
 
-    fn get_hashes(text: &String, tag: Tag) -> Result<Vec<String>, GenericError> {
+    fn get_hashes(text: &str, tag: Tag) -> Result<Vec<String>, GenericError> {
         let mut hashes = Vec::new();
         let regex = tag.as_regex()?;
-        for caps in regex.captures_iter(&text) {
+        for caps in regex.captures_iter(text) {
             let digest = digest::digest(&digest::SHA256, caps["content"].as_bytes());
             let base64 = BASE64.encode(digest.as_ref());
             hashes.push(format!("'sha256-{}'", base64));
@@ -134,7 +168,7 @@ impl Template {
     }
 }
 
-enum Tag {
+pub enum Tag {
     Script,
     Style,
 }
@@ -0,0 +1,286 @@
+use crate::errors::{GenericError, PathError};
+use crate::reload;
+use crate::request::{self, Request};
+use crate::server::{Connection, Routes};
+use log::{error, info};
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::mpsc;
+use tokio_rustls::rustls::internal::pemfile;
+use tokio_rustls::rustls::sign::{self, CertifiedKey};
+use tokio_rustls::rustls::{ClientHello, NoClientAuth, ResolvesServerCert, ServerConfig};
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::TlsAcceptor;
+use warp::http::header::HeaderValue;
+use warp::http::StatusCode;
+use warp::hyper::Body;
+use warp::reply::{Reply, Response};
+use warp::Filter;
+
+/// Clients that take longer than this to finish the handshake are dropped.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The certificate chain and private key served to clients. They can be
+/// replaced while the server is running.
+pub struct Certificates {
+    cert: PathBuf,
+    key: PathBuf,
+    current: RwLock<CertifiedKey>,
+}
+
+impl Certificates {
+    pub fn load(cert: &Path, key: &Path) -> Result<Certificates, GenericError> {
+        Ok(Certificates {
+            cert: cert.to_owned(),
+            key: key.to_owned(),
+            current: RwLock::new(read(cert, key)?),
+        })
+    }
+
+    /// Read the certificate and key again. On error the previous ones are
+    /// kept.
+    pub fn reload(&self) -> Result<(), GenericError> {
+        let certified_key = read(&self.cert, &self.key)?;
+        *self.current.write().unwrap() = certified_key;
+        Ok(())
+    }
+
+    pub fn acceptor(self: &Arc<Self>) -> TlsAcceptor {
+        let mut config = ServerConfig::new(NoClientAuth::new());
+        config.cert_resolver = self.clone();
+        config.set_protocols(&[b"h2".to_vec(), b"http/1.1".to_vec()]);
+        TlsAcceptor::from(Arc::new(config))
+    }
+}
+
+impl ResolvesServerCert for Certificates {
+    fn resolve(&self, _client_hello: ClientHello) -> Option<CertifiedKey> {
+        Some(self.current.read().unwrap().clone())
+    }
+}
+
+fn read(cert: &Path, key: &Path) -> Result<CertifiedKey, GenericError> {
+    let open = |path: &Path| match File::open(path) {
+        Ok(file) => Ok(BufReader::new(file)),
+        Err(e) => Err(PathError::new(path.to_owned(), e.to_string())),
+    };
+
+    let chain = match pemfile::certs(&mut open(cert)?) {
+        Ok(chain) if !chain.is_empty() => chain,
+        _ => Err(PathError::new(
+            cert.to_owned(),
+            "no PEM encoded certificates found",
+        ))?,
+    };
+
+    let mut keys = pemfile::pkcs8_private_keys(&mut open(key)?).unwrap_or_default();
+    if keys.is_empty() {
+        keys = pemfile::rsa_private_keys(&mut open(key)?).unwrap_or_default();
+    }
+    let signing_key = match keys.first().map(sign::any_supported_type) {
+        Some(Ok(signing_key)) => signing_key,
+        Some(Err(_)) => Err(PathError::new(
+            key.to_owned(),
+            "unsupported private key type",
+        ))?,
+        None => Err(PathError::new(
+            key.to_owned(),
+            "no PEM encoded PKCS#8 or RSA private key found",
+        ))?,
+    };
+
+    Ok(CertifiedKey::new(chain, Arc::new(signing_key)))
+}
+
+/// Reload the certificate and key whenever either of the files change.
+pub async fn watch(certificates: Arc<Certificates>) {
+    let paths = vec![certificates.cert.clone(), certificates.key.clone()];
+    let mut previous = reload::fingerprint(&paths);
+    loop {
+        tokio::time::sleep(reload::POLL_INTERVAL).await;
+        let current = reload::fingerprint(&paths);
+        if current == previous {
+            continue;
+        }
+        previous = current;
+
+        match certificates.reload() {
+            Ok(()) => info!("Reloaded the tls certificate"),
+            Err(e) => {
+                error!(
+                    "Reloading the tls certificate failed, still using the previous one. {}",
+                    e
+                );
+            }
+        }
+    }
+}
+
+/// Do the tls handshake for the connections from `incoming`, until the
+/// returned receiver is dropped.
+pub fn accept<S>(
+    mut incoming: mpsc::Receiver<Connection<S>>,
+    acceptor: TlsAcceptor,
+) -> mpsc::Receiver<Connection<TlsStream<S>>>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let (sender, receiver) = mpsc::channel(32);
+    tokio::spawn(async move {
+        loop {
+            let connection = tokio::select! {
+                connection = incoming.recv() => match connection {
+                    Some(connection) => connection,
+                    None => return,
+                },
+                _ = sender.closed() => return,
+            };
+
+            // Handshake in a separate task, so a slow client does not hold up
+            // the others
+            let acceptor = acceptor.clone();
+            let sender = sender.clone();
+            tokio::spawn(async move {
+                let handshake =
+                    tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(connection.stream));
+                if let Ok(Ok(stream)) = handshake.await {
+                    let _ = sender
+                        .send(Connection::new(stream, connection.remote))
+                        .await;
+                }
+            });
+        }
+    });
+    receiver
+}
+
+/// Routes that redirect every request to the same url over https on `port`.
+pub fn redirect(port: u16) -> Routes {
+    request::request()
+        .map(move |request: Request| Box::new(redirect_reply(&request, port)) as Box<dyn Reply>)
+        .boxed()
+}
+
+fn redirect_reply(request: &Request, port: u16) -> Response {
+    let host = request.header("host").map(strip_port);
+    let location = host.and_then(|host| {
+        let authority = if port == 443 {
+            host.to_owned()
+        } else {
+            format!("{}:{}", host, port)
+        };
+        HeaderValue::from_str(&format!(
+            "https://{}{}",
+            authority,
+            request.path_and_query()
+        ))
+        .ok()
+    });
+
+    let mut response = Response::new(Body::empty());
+    match location {
+        Some(location) => {
+            *response.status_mut() = StatusCode::PERMANENT_REDIRECT;
+            response.headers_mut().insert("Location", location);
+        }
+        None => *response.status_mut() = StatusCode::BAD_REQUEST,
+    }
+    response
+}
+
+/// The host part of a Host header, without the port.
+fn strip_port(host: &str) -> &str {
+    if host.starts_with('[') {
+        // IPv6 literal
+        match host.find(']') {
+            Some(end) => &host[..=end],
+            None => host,
+        }
+    } else {
+        host.split(':').next().unwrap_or(host)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use warp::http::header::HOST;
+    use warp::http::{HeaderMap, Uri};
+
+    fn redirect(uri: &str, host: Option<&str>, port: u16) -> (StatusCode, Option<String>) {
+        let mut headers = HeaderMap::new();
+        if let Some(host) = host {
+            headers.insert(HOST, HeaderValue::from_str(host).unwrap());
+        }
+        let request = Request::new(&uri.parse::<Uri>().unwrap(), headers);
+        let response = redirect_reply(&request, port);
+        let location = response
+            .headers()
+            .get("Location")
+            .map(|value| value.to_str().unwrap().to_owned());
+        (response.status(), location)
+    }
+
+    #[test]
+    fn strip_port() {
+        assert_eq!(super::strip_port("example.com"), "example.com");
+        assert_eq!(super::strip_port("example.com:8080"), "example.com");
+        assert_eq!(super::strip_port("127.0.0.1:80"), "127.0.0.1");
+        assert_eq!(super::strip_port("[::1]"), "[::1]");
+        assert_eq!(super::strip_port("[::1]:8080"), "[::1]");
+        assert_eq!(super::strip_port("[2001:db8::1]:80"), "[2001:db8::1]");
+    }
+
+    #[test]
+    fn redirects_to_https() {
+        assert_eq!(
+            redirect("/", Some("example.com"), 443),
+            (
+                StatusCode::PERMANENT_REDIRECT,
+                Some("https://example.com/".to_owned())
+            )
+        );
+        // The plain http port is replaced by the https one
+        assert_eq!(
+            redirect("/", Some("example.com:8080"), 8443),
+            (
+                StatusCode::PERMANENT_REDIRECT,
+                Some("https://example.com:8443/".to_owned())
+            )
+        );
+        assert_eq!(
+            redirect("/", Some("[::1]:80"), 443),
+            (
+                StatusCode::PERMANENT_REDIRECT,
+                Some("https://[::1]/".to_owned())
+            )
+        );
+        assert_eq!(
+            redirect("/", Some("[::1]"), 8443),
+            (
+                StatusCode::PERMANENT_REDIRECT,
+                Some("https://[::1]:8443/".to_owned())
+            )
+        );
+    }
+
+    #[test]
+    fn path_and_query_are_kept() {
+        assert_eq!(
+            redirect("/a/b.html?x=1&y=%20", Some("example.com"), 443),
+            (
+                StatusCode::PERMANENT_REDIRECT,
+                Some("https://example.com/a/b.html?x=1&y=%20".to_owned())
+            )
+        );
+    }
+
+    #[test]
+    fn missing_host_is_rejected() {
+        assert_eq!(redirect("/", None, 443), (StatusCode::BAD_REQUEST, None));
+    }
+}
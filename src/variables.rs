@@ -0,0 +1,173 @@
+use crate::escape::Escape;
+use serde_derive::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+/// A value that can be used in the template, either given in the config or
+/// taken from the request.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum Value {
+    Text(String),
+    Integer(i64),
+    Float(f64),
+    Boolean(bool),
+    List(Vec<Value>),
+    Map(HashMap<String, Value>),
+}
+
+impl Value {
+    /// Whether the value counts as set in `{% if %}` and `default(...)`.
+    pub fn is_truthy(&self) -> bool {
+        match self {
+            Value::Text(text) => !text.is_empty(),
+            Value::Integer(_) | Value::Float(_) => true,
+            Value::Boolean(b) => *b,
+            Value::List(list) => !list.is_empty(),
+            Value::Map(map) => !map.is_empty(),
+        }
+    }
+
+    pub fn write(&self, escape: Escape, out: &mut String) {
+        match self {
+            Value::Text(text) => escape.write(text, out),
+            Value::Integer(i) => escape.write(&i.to_string(), out),
+            Value::Float(f) => escape.write(&f.to_string(), out),
+            Value::Boolean(b) => escape.write(&b.to_string(), out),
+            Value::List(list) => {
+                for (i, item) in list.iter().enumerate() {
+                    if i > 0 {
+                        escape.write(", ", out);
+                    }
+                    item.write(escape, out);
+                }
+            }
+            Value::Map(_) => {}
+        }
+    }
+
+    /// Follow a dot-separated path through nested maps.
+    fn path(&self, path: &str) -> Option<&Value> {
+        let mut value = self;
+        for key in path.split('.') {
+            value = match value {
+                Value::Map(map) => map.get(key)?,
+                _ => return None,
+            }
+        }
+        Some(value)
+    }
+
+    /// Template names are always lowercase, so keys coming from the config
+    /// are lowercased as well.
+    fn lowercase_keys(self) -> Value {
+        match self {
+            Value::List(list) => Value::List(list.into_iter().map(Value::lowercase_keys).collect()),
+            Value::Map(map) => Value::Map(
+                map.into_iter()
+                    .map(|(k, v)| (k.to_lowercase(), v.lowercase_keys()))
+                    .collect(),
+            ),
+            value => value,
+        }
+    }
+}
+
+impl From<String> for Value {
+    fn from(text: String) -> Value {
+        Value::Text(text)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(text: &str) -> Value {
+        Value::Text(text.to_owned())
+    }
+}
+
+impl From<Cow<'_, str>> for Value {
+    fn from(text: Cow<'_, str>) -> Value {
+        Value::Text(text.into_owned())
+    }
+}
+
+/// Values available to the template when it is rendered.
+#[derive(Debug, Clone, Default)]
+pub struct Variables {
+    map: HashMap<String, Value>,
+}
+
+impl Variables {
+    pub fn insert<K: Into<String>, V: Into<Value>>(&mut self, key: K, value: V) {
+        self.map.insert(key.into(), value.into());
+    }
+
+    /// Copy all variables of `other`, replacing any with the same name.
+    pub fn extend(&mut self, other: &Variables) {
+        for (key, value) in &other.map {
+            self.map.insert(key.clone(), value.clone());
+        }
+    }
+
+    /// Look up a variable by its full name, or a value nested inside one,
+    /// eg. `service.name` when `service` is a map.
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        if let Some(value) = self.map.get(name) {
+            return Some(value);
+        }
+        name.rmatch_indices('.').find_map(|(i, _)| {
+            self.map
+                .get(&name[..i])
+                .and_then(|value| value.path(&name[i + 1..]))
+        })
+    }
+}
+
+impl From<HashMap<String, Value>> for Variables {
+    fn from(map: HashMap<String, Value>) -> Variables {
+        Variables {
+            map: map
+                .into_iter()
+                .map(|(k, v)| (k.to_lowercase(), v.lowercase_keys()))
+                .collect(),
+        }
+    }
+}
+
+/// Variables together with the loop variables currently in scope.
+pub struct Scope<'a> {
+    variables: &'a Variables,
+    locals: Vec<(&'a str, &'a Value)>,
+}
+
+impl<'a> Scope<'a> {
+    pub fn new(variables: &'a Variables) -> Self {
+        Scope {
+            variables,
+            locals: Vec::new(),
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&'a Value> {
+        for (local, value) in self.locals.iter().rev() {
+            if name == *local {
+                return Some(value);
+            }
+            if let Some(rest) = name
+                .strip_prefix(local)
+                .and_then(|rest| rest.strip_prefix('.'))
+            {
+                return value.path(rest);
+            }
+        }
+        self.variables.get(name)
+    }
+
+    pub fn push(&mut self, name: &'a str, value: &'a Value) {
+        self.locals.push((name, value));
+    }
+
+    pub fn pop(&mut self) {
+        self.locals.pop();
+    }
+}